@@ -0,0 +1,222 @@
+use std::f32::consts::TAU;
+
+use cgmath::{InnerSpace, Quaternion, Rad, Rotation, Rotation3, Vector3};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_CAMERA_PATH: &str = "assets/camera_path.ron";
+
+/// Camera pose at a point in time, in seconds from the start of the path.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub time: f32,
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    #[serde(default = "default_fovy")]
+    pub fovy: f32, // degrees
+}
+
+/// Keyframes played back with a Catmull-Rom spline through all of them, e.g. a turntable
+/// around a volume or a recorded fly-through. Stored as RON like the scene files, see
+/// `ron_file`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>, // sorted by time
+    #[serde(default)]
+    pub looping: bool, // the last keyframe should repeat the first
+}
+
+fn default_fovy() -> f32 {
+    45.0
+}
+
+impl Keyframe {
+    fn values(&self) -> [f32; 7] {
+        let (e, t) = (self.eye, self.target);
+        [e[0], e[1], e[2], t[0], t[1], t[2], self.fovy]
+    }
+
+    fn from_values(time: f32, v: [f32; 7]) -> Self {
+        Keyframe {
+            time,
+            eye: [v[0], v[1], v[2]],
+            target: [v[3], v[4], v[5]],
+            fovy: v[6],
+        }
+    }
+}
+
+impl CameraPath {
+    /// One revolution of `eye` around the axis `up` through `center` in `duration` seconds,
+    /// looping.
+    pub fn turntable(center: Vector3<f32>, eye: Vector3<f32>, up: Vector3<f32>, fovy: f32, duration: f32) -> Self {
+        const STEPS: u32 = 16;
+        let axis = up.normalize();
+        let keyframes = (0..=STEPS)
+            .map(|i| {
+                let turn = i as f32 / STEPS as f32;
+                let rotation = Quaternion::from_axis_angle(axis, Rad(turn * TAU));
+                Keyframe {
+                    time: turn * duration,
+                    eye: (center + rotation.rotate_vector(eye - center)).into(),
+                    target: center.into(),
+                    fovy,
+                }
+            })
+            .collect();
+        CameraPath { keyframes, looping: true }
+    }
+
+    pub fn duration(&self) -> f32 {
+        match (self.keyframes.first(), self.keyframes.last()) {
+            (Some(first), Some(last)) => last.time - first.time,
+            _ => 0.0,
+        }
+    }
+
+    /// Appends a keyframe `interval` seconds after the last one.
+    pub fn push(&mut self, eye: Vector3<f32>, target: Vector3<f32>, fovy: f32, interval: f32) {
+        let time = self.keyframes.last().map_or(0.0, |k| k.time + interval);
+        self.keyframes.push(Keyframe {
+            time,
+            eye: eye.into(),
+            target: target.into(),
+            fovy,
+        });
+    }
+
+    /// The pose `time` seconds after the first keyframe. Clamps to the ends, or wraps around
+    /// for looping paths.
+    pub fn sample(&self, time: f32) -> Option<Keyframe> {
+        let keys = &self.keyframes;
+        let (first, last) = (keys.first()?, keys.last()?);
+        let duration = self.duration();
+        if keys.len() == 1 || duration <= 0.0 {
+            return Some(*first);
+        }
+
+        let mut time = first.time + time;
+        if self.looping {
+            time = first.time + (time - first.time).rem_euclid(duration);
+        }
+        let time = time.clamp(first.time, last.time);
+
+        // Hermite segment between keys i and i + 1
+        let i = keys.partition_point(|k| k.time <= time).clamp(1, keys.len() - 1) - 1;
+        let h = keys[i + 1].time - keys[i].time;
+        if h <= 0.0 {
+            return Some(keys[i + 1]);
+        }
+        let s = (time - keys[i].time) / h;
+        let (s2, s3) = (s * s, s * s * s);
+        let h00 = 2.0 * s3 - 3.0 * s2 + 1.0;
+        let h10 = s3 - 2.0 * s2 + s;
+        let h01 = -2.0 * s3 + 3.0 * s2;
+        let h11 = s3 - s2;
+
+        let (p0, p1) = (keys[i].values(), keys[i + 1].values());
+        let (m0, m1) = (self.tangent(i), self.tangent(i + 1));
+        let mut values = [0.0; 7];
+        for (c, v) in values.iter_mut().enumerate() {
+            *v = h00 * p0[c] + h10 * h * m0[c] + h01 * p1[c] + h11 * h * m1[c];
+        }
+        Some(Keyframe::from_values(time - first.time, values))
+    }
+
+    // Catmull-Rom tangent for uneven key spacing, one sided at the ends of an open path
+    fn tangent(&self, i: usize) -> [f32; 7] {
+        let keys = &self.keyframes;
+        let n = keys.len();
+        let duration = self.duration();
+        let (prev, prev_time) = match i {
+            0 if self.looping && n > 2 => (keys[n - 2].values(), keys[n - 2].time - duration),
+            0 => (keys[0].values(), keys[0].time),
+            _ => (keys[i - 1].values(), keys[i - 1].time),
+        };
+        let (next, next_time) = match i {
+            _ if i == n - 1 && self.looping && n > 2 => (keys[1].values(), keys[1].time + duration),
+            _ if i == n - 1 => (keys[i].values(), keys[i].time),
+            _ => (keys[i + 1].values(), keys[i + 1].time),
+        };
+
+        let dt = next_time - prev_time;
+        let mut tangent = [0.0; 7];
+        if dt > 0.0 {
+            for (c, m) in tangent.iter_mut().enumerate() {
+                *m = (next[c] - prev[c]) / dt;
+            }
+        }
+        tangent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_pose(a: &Keyframe, b: &Keyframe) {
+        for (x, y) in a.values().iter().zip(b.values()) {
+            assert!((x - y).abs() < 1e-3, "{a:?} != {b:?}");
+        }
+    }
+
+    fn recorded() -> CameraPath {
+        let key = |time, eye, target, fovy| Keyframe { time, eye, target, fovy };
+        CameraPath {
+            keyframes: vec![
+                key(0.5, [0.0, 0.0, 5.0], [0.0, 0.0, 0.0], 45.0),
+                key(1.0, [3.0, 1.0, 4.0], [0.5, 0.0, 0.0], 40.0),
+                key(2.5, [5.0, 2.0, 0.0], [0.5, 1.0, 0.0], 30.0),
+                key(2.75, [4.0, -1.0, -3.0], [0.0, 1.0, 0.0], 35.0),
+                key(4.0, [0.0, 0.0, -5.0], [0.0, 0.0, 0.0], 45.0),
+            ],
+            looping: false,
+        }
+    }
+
+    #[test]
+    fn spline_passes_through_the_keyframes() {
+        for looping in [false, true] {
+            let path = CameraPath { looping, ..recorded() };
+            let start = path.keyframes[0].time;
+            // A looping path wraps its end around to the start, which differs here
+            let keys = if looping { &path.keyframes[..path.keyframes.len() - 1] } else { &path.keyframes[..] };
+            for key in keys {
+                assert_pose(&path.sample(key.time - start).unwrap(), key);
+            }
+        }
+    }
+
+    #[test]
+    fn sampling_clamps_an_open_path_to_its_ends() {
+        let path = recorded();
+        assert_pose(&path.sample(-1.0).unwrap(), &path.keyframes[0]);
+        assert_pose(&path.sample(10.0).unwrap(), path.keyframes.last().unwrap());
+    }
+
+    #[test]
+    fn turntable_closes() {
+        let center = Vector3::new(1.0, 2.0, 3.0);
+        let path = CameraPath::turntable(center, Vector3::new(1.0, 2.0, 8.0), Vector3::unit_y(), 45.0, 6.0);
+        let (first, last) = (path.keyframes[0], *path.keyframes.last().unwrap());
+        assert_pose(&Keyframe { time: first.time, ..last }, &first);
+        assert_eq!(path.duration(), 6.0);
+
+        let start = path.sample(0.0).unwrap();
+        assert_pose(&path.sample(6.0).unwrap(), &start);
+        // No jump where the loop wraps around
+        let before = path.sample(6.0 - 1e-3).unwrap();
+        let after = path.sample(1e-3).unwrap();
+        assert!((Vector3::from(before.eye) - Vector3::from(after.eye)).magnitude() < 0.1);
+    }
+
+    #[test]
+    fn turntable_keeps_its_distance() {
+        let center = Vector3::new(0.0, 0.0, 0.0);
+        let path = CameraPath::turntable(center, Vector3::new(0.0, 1.0, 4.0), Vector3::unit_y(), 45.0, 8.0);
+        for step in 0..32 {
+            let pose = path.sample(step as f32 * 0.25).unwrap();
+            let distance = (Vector3::from(pose.eye) - Vector3::new(0.0, 1.0, 0.0)).magnitude();
+            assert!((distance - 4.0).abs() < 0.05, "{distance}");
+        }
+    }
+}
@@ -5,14 +5,21 @@ use instant::Instant;
 #[cfg(not(web_platform))]
 use std::time::Duration;
 
+use cgmath::Vector3;
+
 use winit::application::ApplicationHandler;
 use winit::dpi::PhysicalSize;
-use winit::event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, StartCause, WindowEvent};
+use winit::event::{ElementState, KeyEvent, MouseScrollDelta, StartCause, WindowEvent};
 use winit::event_loop::{ActiveEventLoop, EventLoop};
-use winit::keyboard::Key;
 use winit::window::{Window, WindowAttributes, WindowId};
 
+use crate::animation::DEFAULT_CAMERA_PATH;
+use crate::camera::{AnatomicalView, CameraMode, Projection};
+use crate::environment::DEFAULT_ENVIRONMENT_PATH;
+use crate::input::{Action, Bindings, Input, DEFAULT_BINDINGS_PATH};
 use crate::renderer::Renderer;
+use crate::ron_file;
+use crate::session::DEFAULT_SESSION_PATH;
 
 pub fn run() {
     let event_loop = EventLoop::new().unwrap();
@@ -26,10 +33,84 @@ struct MedicalApp {
     last_update: Option<Instant>,
     window: Option<Arc<Window>>,
     renderer: Option<Renderer>,
+    input: Option<Input>,
     last_cursor_pos: Option<(f64, f64)>,
-    mouse_left_down: bool,
-    mouse_right_down: bool,
-    mouse_middle_down: bool,
+}
+
+impl MedicalApp {
+    fn perform(&mut self, action: Action) {
+        if action == Action::Quit {
+            self.close_requested = true;
+            return;
+        }
+        let Some(renderer) = self.renderer.as_mut() else {
+            return;
+        };
+        match action {
+            Action::ToggleCameraMode => {
+                let mode = match renderer.camera_mode() {
+                    CameraMode::Dolly => CameraMode::Orbit,
+                    CameraMode::Orbit => CameraMode::Dolly,
+                };
+                renderer.set_camera_mode(mode);
+            }
+            Action::ToggleProjection => {
+                let projection = match renderer.projection() {
+                    Projection::Perspective => Projection::Orthograpic,
+                    Projection::Orthograpic => Projection::Perspective,
+                };
+                renderer.set_projection(projection);
+            }
+            Action::Turntable => {
+                if renderer.is_playing_camera_path() {
+                    renderer.stop_camera_path();
+                } else {
+                    renderer.turntable(12.0);
+                }
+            }
+            Action::Record => match renderer.stop_recording() {
+                Some(path) => match ron_file::save(&path, DEFAULT_CAMERA_PATH) {
+                    Ok(()) => println!("saved {} keyframes to {DEFAULT_CAMERA_PATH}", path.keyframes.len()),
+                    Err(e) => println!("cannot save {DEFAULT_CAMERA_PATH}: {e}"),
+                },
+                None => renderer.start_recording(),
+            },
+            Action::PlayCameraPath => {
+                if let Err(e) = renderer.open_camera_path(DEFAULT_CAMERA_PATH) {
+                    println!("{e}");
+                }
+            }
+            Action::SaveSession => match renderer.save_session(DEFAULT_SESSION_PATH) {
+                Ok(()) => println!("saved the session to {DEFAULT_SESSION_PATH}"),
+                Err(e) => println!("cannot save {DEFAULT_SESSION_PATH}: {e}"),
+            },
+            Action::LoadSession => {
+                if let Err(e) = renderer.open_session(DEFAULT_SESSION_PATH) {
+                    println!("{e}");
+                }
+            }
+            Action::LoadEnvironment => match renderer.open_environment(DEFAULT_ENVIRONMENT_PATH) {
+                Ok(()) => println!("lighting with {DEFAULT_ENVIRONMENT_PATH}"),
+                Err(e) => println!("cannot read {DEFAULT_ENVIRONMENT_PATH}: {e}"),
+            },
+            Action::ResetView => renderer.reset_view(),
+            Action::ViewAnterior => renderer.view_from(AnatomicalView::Anterior),
+            Action::ViewPosterior => renderer.view_from(AnatomicalView::Posterior),
+            Action::ViewLeft => renderer.view_from(AnatomicalView::Left),
+            Action::ViewRight => renderer.view_from(AnatomicalView::Right),
+            Action::ViewSuperior => renderer.view_from(AnatomicalView::Superior),
+            Action::ViewInferior => renderer.view_from(AnatomicalView::Inferior),
+            Action::CycleMedicalMode => renderer.cycle_medical_mode(),
+            Action::ToggleLabel(label) => renderer.toggle_label(label),
+            Action::CycleBlendMode => renderer.cycle_blend_mode(),
+            Action::ShiftIsoValue(delta) => renderer.shift_iso_value(delta as f32),
+            Action::CycleSurfaceLabel => renderer.cycle_surface_label(),
+            Action::ExportSurface => renderer.export_surface(),
+            Action::ShiftFusion([x, y, z]) => renderer.shift_fusion(Vector3::new(x as f32, y as f32, z as f32)),
+            // Held actions are read by the camera every frame
+            _ => {}
+        }
+    }
 }
 
 impl ApplicationHandler for MedicalApp {
@@ -52,6 +133,11 @@ impl ApplicationHandler for MedicalApp {
 
         self.window = Some(window.clone());
         self.renderer = Some(Renderer::new(window.clone()).block_on());
+        let bindings = ron_file::load(DEFAULT_BINDINGS_PATH).unwrap_or_else(|e| {
+            println!("{e}, using the default bindings");
+            Bindings::default()
+        });
+        self.input = Some(Input::new(bindings));
         self.last_update = Some(Instant::now());
     }
 
@@ -65,24 +151,27 @@ impl ApplicationHandler for MedicalApp {
             WindowEvent::CloseRequested => {
                 self.close_requested = true;
             },
+            WindowEvent::Resized(size) => {
+                if let Some(renderer) = self.renderer.as_mut() {
+                    renderer.resize(size.width, size.height);
+                }
+            }
+            WindowEvent::ScaleFactorChanged { .. } => {
+                // The physical size follows the new scale factor
+                if let (Some(renderer), Some(window)) = (self.renderer.as_mut(), self.window.as_ref()) {
+                    let size = window.inner_size();
+                    renderer.resize(size.width, size.height);
+                }
+            }
+            WindowEvent::Focused(false) => {
+                if let Some(input) = self.input.as_mut() {
+                    input.release_all();
+                }
+            }
             WindowEvent::MouseInput { state, button, .. } => {
                 let pressed = state == ElementState::Pressed;
-                match button {
-                    MouseButton::Left => {
-                        self.mouse_left_down = pressed;
-                    }
-                    MouseButton::Right => {
-                        self.mouse_right_down = pressed;
-                    }
-                    MouseButton::Middle => {
-                        self.mouse_middle_down = pressed;
-                    }
-                    _ => {}
-                }
-                if let Some(renderer) = self.renderer.as_mut() {
-                    // Treat either Left or Right as the "look" button
-                    let look_down = self.mouse_right_down || self.mouse_left_down;
-                    renderer.mouse_buttons(look_down, self.mouse_middle_down);
+                if let Some(action) = self.input.as_mut().and_then(|input| input.mouse_button(button, pressed)) {
+                    self.perform(action);
                 }
             }
             WindowEvent::CursorMoved { position, .. } => {
@@ -90,31 +179,30 @@ impl ApplicationHandler for MedicalApp {
                 if let Some((px, py)) = self.last_cursor_pos {
                     let dx = (x - px) as f32;
                     let dy = (y - py) as f32;
-                    if let Some(renderer) = self.renderer.as_mut() {
-                        renderer.mouse_motion(dx, dy);
+                    if let Some(input) = self.input.as_mut() {
+                        input.mouse_motion(dx, dy);
                     }
                 }
                 self.last_cursor_pos = Some((x, y));
             }
             WindowEvent::MouseWheel { delta, .. } => {
-                let mut y = 0.0f32;
-                match delta {
-                    MouseScrollDelta::LineDelta(_, ly) => y = ly as f32,
-                    MouseScrollDelta::PixelDelta(pos) => y = pos.y as f32,
-                }
-                if let Some(renderer) = self.renderer.as_mut() {
-                    renderer.mouse_wheel(y);
+                let y = match delta {
+                    MouseScrollDelta::LineDelta(_, ly) => ly,
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32,
+                };
+                if let Some(input) = self.input.as_mut() {
+                    input.mouse_wheel(y);
                 }
             }
             WindowEvent::KeyboardInput {
-                event: KeyEvent { logical_key: key, state: ElementState::Pressed, .. },
+                event: KeyEvent { physical_key, state, repeat, .. },
                 ..
-            } => match key.as_ref() {
-                Key::Character("Q") | Key::Character("q") => {
-                    self.close_requested = true;
-                },
-                _ => (),
-            },
+            } => {
+                let pressed = state == ElementState::Pressed;
+                if let Some(action) = self.input.as_mut().and_then(|input| input.key(physical_key, pressed, repeat)) {
+                    self.perform(action);
+                }
+            }
         WindowEvent::RedrawRequested => {
                 let window = self.window.as_ref().unwrap();
                 window.pre_present_notify();
@@ -133,11 +221,29 @@ impl ApplicationHandler for MedicalApp {
             return;
         }
 
+        let actions = self.input.as_mut().unwrap().poll_gamepad();
+        for action in actions {
+            self.perform(action);
+        }
+        let camera_input = self.input.as_mut().unwrap().camera_input();
+
         let renderer = self.renderer.as_mut().unwrap();
         let window = self.window.as_ref().unwrap();
 
-        renderer.update();
-        let _ = renderer.render();
+        renderer.update(&camera_input);
+        match renderer.render() {
+            Ok(()) => {}
+            // Reconfigure and draw again next frame instead of presenting a stale image
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                let size = window.inner_size();
+                renderer.resize(size.width, size.height);
+            }
+            Err(wgpu::SurfaceError::OutOfMemory) => {
+                println!("out of memory, exiting");
+                self.close_requested = true;
+            }
+            Err(e) => println!("{e}"),
+        }
 
         #[cfg(not(web_platform))]
         {
@@ -1,8 +1,11 @@
-use cgmath::{InnerSpace, Vector3};
-use gilrs::{Axis, Button, Event, EventType, Gilrs};
+use cgmath::{InnerSpace, Matrix3, Matrix4, Quaternion, SquareMatrix, Vector3};
 use instant::Instant;
+use serde::{Deserialize, Serialize};
 use wgpu::util::DeviceExt;
 
+use crate::animation::CameraPath;
+use crate::input::CameraInput;
+
 #[repr(C)]
 #[derive(Debug, Copy, Default, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
@@ -20,77 +23,144 @@ pub struct CameraUniform {
     _pad3: f32,
     lower_left_corner: [f32; 3],
     _pad4: f32,
+    view_proj: [[f32; 4]; 4], // for rasterized geometry, matches the rays above
+    inv_view_proj: [[f32; 4]; 4],
 }
 
+// cgmath produces OpenGL clip space depth (-1..1), wgpu expects 0..1
+#[rustfmt::skip]
+const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
 pub struct Camera {
     pub eye: cgmath::Vector3<f32>,
     pub target: cgmath::Vector3<f32>,
     pub up: cgmath::Vector3<f32>,
     pub aspect: f32,
     pub fovy: f32,
-    pub znear: f32,
+    pub znear: f32, // follow the bounds once they are set, see set_bounds()
     pub zfar: f32,
     pub aperture: f32,
     pub focus_distance: f32,
     pub projection: Projection,
+    pub ortho_height: f32, // visible height in world units with the orthographic projection
+    pub mode: CameraMode,
 
-    // Dolly camera state
+    // Shared by both modes: the viewing direction
     yaw: f32,
     pitch: f32,
-    move_speed: f32,       // units per second
-    look_sensitivity: f32, // radians per second at full deflection
+
+    // Orbit camera state, the eye sits `distance` behind the pivot along the view direction
+    pivot: Vector3<f32>,
+    distance: f32,
+    zoom_speed: f32, // fraction of the distance per wheel unit
+
+    // Centre and radius of everything there is to see, in world units
+    bounds: Option<(Vector3<f32>, f32)>,
+
+    // Animated move to a view, see animate_to()
+    transition: Option<Transition>,
+    transition_duration: f32, // seconds
+
+    // Keyframe playback replaces all input, recording samples the camera as it moves
+    playback: Option<(CameraPath, f32)>, // path and seconds since its start
+    recording: Option<(CameraPath, f32)>, // path and seconds since its last keyframe
+    record_interval: f32,
+
+    // Dolly camera state
+    move_speed: f32,   // units per second
+    scroll_speed: f32, // units per wheel unit
     last_update: Instant,
 
     pub uniform: CameraUniform,
     pub buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
-
-    gilrs: Gilrs,
-    dpaddown: bool,
-    dpadup: bool,
-    left_x: f32,
-    left_y: f32,
-    right_x: f32,
-    right_y: f32,
-
-    // Mouse interaction state
-    mouse_right_down: bool,
-    mouse_middle_down: bool,
-    mouse_dx: f32,
-    mouse_dy: f32,
-    scroll: f32,
-    mouse_look_sensitivity: f32, // radians per pixel
-    mouse_pan_sensitivity: f32,  // units per pixel
-    scroll_speed: f32,           // units per wheel unit
-    invert_look_y: bool,
-    invert_pan_y: bool,
-    invert_look_x: bool,
-    invert_pan_x: bool,
 }
 
 #[repr(u32)]
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Projection {
     Orthograpic = 0,
     Perspective = 1,
 }
 
-// Dolly-style camera (first-person-like) implemented in update()
+/// Where an orbiting camera looks from: the pivot, the viewing direction and screen up, and
+/// the distance of the eye from the pivot.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraView {
+    pub pivot: Vector3<f32>,
+    pub forward: Vector3<f32>,
+    pub up: Vector3<f32>,
+    pub distance: f32,
+}
 
-impl Camera {
-    pub fn new(device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration) -> Self {
-        let gilrs = Gilrs::new().unwrap();
-        for (_id, gamepad) in gilrs.gamepads() {
-            println!("{} is {:?}", gamepad.name(), gamepad.power_info());
+/// The parts of a camera that make up the view, for saving it and restoring it later.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct CameraState {
+    pub eye: [f32; 3],
+    pub target: [f32; 3], // the pivot when orbiting
+    pub up: [f32; 3],     // screen up
+    pub fovy: f32,
+    pub projection: Projection,
+    pub ortho_height: f32,
+    pub mode: CameraMode,
+}
+
+struct Transition {
+    from: CameraView,
+    to: CameraView,
+    elapsed: f32,
+}
+
+/// Standard views of a patient, named after the side the camera looks from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnatomicalView {
+    Anterior,
+    Posterior,
+    Left,
+    Right,
+    Superior,
+    Inferior,
+}
+
+impl AnatomicalView {
+    /// Viewing direction and screen up in patient coordinates (RAS: +x right, +y anterior,
+    /// +z superior). The head is up from the sides, the front is up from above and below.
+    pub fn orientation(self) -> (Vector3<f32>, Vector3<f32>) {
+        let (x, y, z) = (Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z());
+        match self {
+            AnatomicalView::Anterior => (-y, z),
+            AnatomicalView::Posterior => (y, z),
+            AnatomicalView::Left => (x, z),
+            AnatomicalView::Right => (-x, z),
+            AnatomicalView::Superior => (-z, y),
+            AnatomicalView::Inferior => (z, y),
         }
+    }
+}
+
+/// How mouse and gamepad input move the camera, see update().
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CameraMode {
+    /// First person: looking turns the view, the sticks and the wheel move the eye.
+    Dolly,
+    /// Looking rotates the eye around a pivot, the wheel zooms towards it and panning moves it.
+    Orbit,
+}
 
+impl Camera {
+    pub fn new(device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration) -> Self {
         let uniform = CameraUniform::default();
 
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[wgpu::BindGroupLayoutEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -137,56 +207,227 @@ impl Camera {
             aperture: 0.5,
             focus_distance: 1.0,
             projection: Projection::Perspective,
+            ortho_height: 2.0,
+            mode: CameraMode::Dolly,
 
             yaw,
             pitch,
+
+            pivot: target,
+            distance: (target - eye).magnitude(),
+            zoom_speed: 0.1,
+
+            bounds: None,
+
+            transition: None,
+            transition_duration: 0.6,
+
+            playback: None,
+            recording: None,
+            record_interval: 0.5,
+
             move_speed: 0.8,
-            look_sensitivity: 0.8,
+            scroll_speed: 0.6,
             last_update: Instant::now(),
 
             uniform,
             buffer,
             bind_group,
             bind_group_layout,
+        }
+    }
 
-            gilrs,
-            dpaddown: false,
-            dpadup: false,
-            left_x: 0.0,
-            right_x: 0.0,
-            left_y: 0.0,
-            right_y: 0.0,
-
-            mouse_right_down: false,
-            mouse_middle_down: false,
-            mouse_dx: 0.0,
-            mouse_dy: 0.0,
-            scroll: 0.0,
-            mouse_look_sensitivity: 0.0035,
-            mouse_pan_sensitivity: 0.003,
-            scroll_speed: 0.6,
-            invert_look_y: true,
-            invert_pan_y: true,
-            invert_look_x: true,
-            invert_pan_x: true,
+    /// Places the camera at `eye` looking at `target`, which becomes the orbit pivot.
+    pub fn look_at(&mut self, eye: Vector3<f32>, target: Vector3<f32>) {
+        self.eye = eye;
+        self.target = target;
+        self.set_forward((target - eye).normalize());
+        self.pivot = target;
+        self.distance = (target - eye).magnitude();
+    }
+
+    pub fn state(&self) -> CameraState {
+        CameraState {
+            eye: self.eye.into(),
+            target: self.target.into(),
+            up: (-self.up).into(),
+            fovy: self.fovy,
+            projection: self.projection,
+            ortho_height: self.ortho_height,
+            mode: self.mode,
+        }
+    }
+
+    /// Jumps to a saved view, ending any animation.
+    pub fn set_state(&mut self, state: &CameraState) {
+        self.transition = None;
+        self.playback = None;
+        self.up = -Vector3::from(state.up).normalize();
+        self.mode = state.mode;
+        self.look_at(state.eye.into(), state.target.into());
+        self.fovy = state.fovy;
+        self.projection = state.projection;
+        self.ortho_height = state.ortho_height;
+    }
+
+    /// Follows the new window size, the view itself stays.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.aspect = width as f32 / height as f32;
+    }
+
+    /// The current orbit, also meaningful for the dolly camera which looks at its target.
+    pub fn view(&self) -> CameraView {
+        let forward = self.forward();
+        let right = forward.cross(-self.up);
+        let up = if right.magnitude2() < 1e-6 { -self.up } else { right.cross(forward).normalize() };
+        CameraView {
+            pivot: self.pivot,
+            forward,
+            up,
+            distance: self.distance,
+        }
+    }
+
+    /// Jumps to `view`. Its screen up becomes the axis the camera turns around.
+    pub fn set_view(&mut self, view: CameraView) {
+        self.transition = None;
+        self.apply_view(view);
+    }
+
+    /// Moves to `view` over `transition_duration` seconds in update(), input is ignored
+    /// until it arrives.
+    pub fn animate_to(&mut self, view: CameraView) {
+        self.transition = Some(Transition {
+            from: self.view(),
+            to: view,
+            elapsed: 0.0,
+        });
+    }
+
+    /// Switches projections so that objects at the orbit distance keep their size on screen.
+    pub fn set_projection(&mut self, projection: Projection) {
+        let tan_half_fov = (self.fovy.to_radians() * 0.5).tan();
+        match (self.projection, projection) {
+            (Projection::Perspective, Projection::Orthograpic) => {
+                self.ortho_height = 2.0 * tan_half_fov * self.distance;
+            }
+            (Projection::Orthograpic, Projection::Perspective) => {
+                let distance = self.ortho_height / (2.0 * tan_half_fov);
+                if self.mode == CameraMode::Orbit {
+                    self.orbit(self.pivot, distance);
+                }
+            }
+            _ => {}
+        }
+        self.projection = projection;
+    }
+
+    /// Fits the clip planes around a sphere from now on, wherever the eye moves. The scene may
+    /// be in mm (a CT is hundreds of them across) or in metres, fixed planes suit only one.
+    pub fn set_bounds(&mut self, center: Vector3<f32>, radius: f32) {
+        self.bounds = Some((center, radius.max(1e-3)));
+    }
+
+    /// Orthographic view height at which a sphere of `radius` fills the narrower side.
+    pub fn framing_height(&self, radius: f32) -> f32 {
+        2.0 * radius / self.aspect.min(1.0)
+    }
+
+    /// Flies along `path` in update() until it ends, or until stop_playback() for looping paths.
+    pub fn play(&mut self, path: CameraPath) {
+        self.transition = None;
+        self.playback = Some((path, 0.0));
+    }
+
+    pub fn stop_playback(&mut self) {
+        self.playback = None;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    /// Starts a fly-through: a keyframe now and then every `record_interval` seconds.
+    pub fn start_recording(&mut self) {
+        let mut path = CameraPath::default();
+        path.push(self.eye, self.target, self.fovy, self.record_interval);
+        self.recording = Some((path, 0.0));
+    }
+
+    /// The recorded path, ending at the current view.
+    pub fn stop_recording(&mut self) -> Option<CameraPath> {
+        let (mut path, elapsed) = self.recording.take()?;
+        if elapsed > 0.0 {
+            path.push(self.eye, self.target, self.fovy, elapsed);
+        }
+        Some(path)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Distance from which a sphere of `radius` fills the narrower side of the view.
+    pub fn framing_distance(&self, radius: f32) -> f32 {
+        let half_height = (self.fovy.to_radians() * 0.5).tan();
+        let half_fov = half_height.min(half_height * self.aspect).atan();
+        radius / half_fov.sin()
+    }
+
+    fn apply_view(&mut self, view: CameraView) {
+        self.up = -view.up.normalize();
+        self.set_forward(view.forward.normalize());
+        self.pivot = view.pivot;
+        self.distance = view.distance.max(1e-3);
+        self.eye = self.pivot - self.forward() * self.distance;
+        self.target = self.pivot;
+    }
+
+    /// Switches the controls, keeping the current view. Orbiting starts around the point
+    /// the dolly camera was looking at.
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        if mode == CameraMode::Orbit && self.mode == CameraMode::Dolly {
+            self.pivot = self.target;
+            self.distance = (self.target - self.eye).magnitude();
         }
+        self.mode = mode;
+    }
+
+    pub fn pivot(&self) -> Vector3<f32> {
+        self.pivot
+    }
+
+    /// Orbits around `pivot` from the current viewing direction, at `distance`.
+    pub fn orbit(&mut self, pivot: Vector3<f32>, distance: f32) {
+        self.pivot = pivot;
+        self.distance = distance.max(1e-3);
+        self.eye = pivot - self.forward() * self.distance;
+        self.target = pivot;
     }
 
-    pub fn mouse_motion(&mut self, dx: f32, dy: f32) {
-        self.mouse_dx += dx;
-        self.mouse_dy += dy;
+    fn forward(&self) -> Vector3<f32> {
+        let (e1, e2) = self.horizontal_axes();
+        let cp = self.pitch.cos();
+        (e1 * (self.yaw.cos() * cp) + -self.up * self.pitch.sin() + e2 * (self.yaw.sin() * cp)).normalize()
     }
 
-    pub fn mouse_buttons(&mut self, right: bool, middle: bool) {
-        self.mouse_right_down = right;
-        self.mouse_middle_down = middle;
+    fn set_forward(&mut self, forward: Vector3<f32>) {
+        let (e1, e2) = self.horizontal_axes();
+        self.yaw = forward.dot(e2).atan2(forward.dot(e1));
+        self.pitch = forward.dot(-self.up).clamp(-1.0, 1.0).asin();
     }
 
-    pub fn mouse_wheel(&mut self, delta: f32) {
-        self.scroll += delta;
+    // Yaw turns from the first axis towards the second, x and z for the default y up
+    fn horizontal_axes(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let world_up = -self.up;
+        let reference = if world_up.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_z() };
+        let e1 = (reference - world_up * reference.dot(world_up)).normalize();
+        (e1, e1.cross(world_up))
     }
 
-    pub fn update(&mut self, queue: &wgpu::Queue) {
+    /// Moves the camera by one frame of `input`, unless a camera path or a transition is
+    /// playing.
+    pub fn update(&mut self, queue: &wgpu::Queue, input: &CameraInput) {
         self.aperture = 0.0;
 
         // Delta time
@@ -196,81 +437,125 @@ impl Camera {
         if !dt.is_finite() || dt <= 0.0 { dt = 1.0 / 60.0; }
         dt = dt.min(0.05);
 
-        // Read controller state
-        while let Some(Event { event, .. }) = self.gilrs.next_event() {
-            match event {
-                EventType::ButtonPressed(Button::DPadDown, _) => self.dpaddown = true,
-                EventType::ButtonReleased(Button::DPadDown, _) => self.dpaddown = false,
-                EventType::ButtonPressed(Button::DPadUp, _) => self.dpadup = true,
-                EventType::ButtonReleased(Button::DPadUp, _) => self.dpadup = false,
-
-                EventType::AxisChanged(Axis::LeftStickX, val, _) => self.left_x = val,
-                EventType::AxisChanged(Axis::LeftStickY, val, _) => self.left_y = val,
-                EventType::AxisChanged(Axis::RightStickX, val, _) => self.right_x = val,
-                EventType::AxisChanged(Axis::RightStickY, val, _) => self.right_y = val,
-                _ => {}
+        if let Some((path, mut time)) = self.playback.take() {
+            time += dt;
+            if let Some(key) = path.sample(time) {
+                self.look_at(key.eye.into(), key.target.into());
+                self.fovy = key.fovy;
+            }
+            if path.looping || time < path.duration() {
+                self.playback = Some((path, time));
             }
+            self.finish_update(queue, dt);
+            return;
         }
 
-        // Deadzones
-        let dz = 0.12;
-        let lx = if self.left_x.abs() < dz { 0.0 } else { self.left_x };
-        let ly = if self.left_y.abs() < dz { 0.0 } else { self.left_y };
-        let rx = if self.right_x.abs() < dz { 0.0 } else { self.right_x };
-        let ry = if self.right_y.abs() < dz { 0.0 } else { self.right_y };
-
-        // Look: controller + mouse (right button drag)
-        let mut yaw_delta = rx * self.look_sensitivity * dt;
-        let mut pitch_delta = ry * self.look_sensitivity * dt;
-        if self.mouse_right_down {
-            let x_sign = if self.invert_look_x { -1.0 } else { 1.0 };
-            yaw_delta += (self.mouse_dx * x_sign) * self.mouse_look_sensitivity;
-            let y_sign = if self.invert_look_y { -1.0 } else { 1.0 };
-            pitch_delta += (self.mouse_dy * y_sign) * self.mouse_look_sensitivity;
+        if let Some(mut transition) = self.transition.take() {
+            transition.elapsed += dt;
+            let t = (transition.elapsed / self.transition_duration).min(1.0);
+            self.apply_view(transition.view_at(t * t * (3.0 - 2.0 * t)));
+            if t < 1.0 {
+                self.transition = Some(transition);
+            }
+            self.finish_update(queue, dt);
+            return;
         }
-        self.yaw += yaw_delta;
-        self.pitch += pitch_delta;
+
+        // Look: controller + mouse drag
+        self.yaw += input.look_rate[0] * dt + input.look[0];
+        self.pitch += input.look_rate[1] * dt + input.look[1];
         let max_pitch = 1.54;
         if self.pitch > max_pitch { self.pitch = max_pitch; }
         if self.pitch < -max_pitch { self.pitch = -max_pitch; }
 
         // Basis from yaw/pitch
-        let cp = self.pitch.cos();
-        let forward = Vector3::new(self.yaw.cos() * cp, self.pitch.sin(), self.yaw.sin() * cp).normalize();
+        let forward = self.forward();
         let world_up = -self.up;
         let mut right = forward.cross(world_up);
         if right.magnitude2() < 1e-6 { right = Vector3::new(1.0, 0.0, 0.0); }
         let right = right.normalize();
 
-        // Move: controller left stick (forward/back + strafe)
-        let mut move_dir = forward * (-ly) + right * lx;
+        match self.mode {
+            CameraMode::Dolly => self.move_dolly(dt, forward, right, input),
+            CameraMode::Orbit => self.move_orbit(dt, forward, right, input),
+        }
+
+        self.finish_update(queue, dt);
+    }
+
+    fn finish_update(&mut self, queue: &wgpu::Queue, dt: f32) {
+        if let Some((path, elapsed)) = &mut self.recording {
+            *elapsed += dt;
+            if *elapsed >= self.record_interval {
+                path.push(self.eye, self.target, self.fovy, *elapsed);
+                *elapsed = 0.0;
+            }
+        }
+
+        self.update_uniform(queue);
+    }
+
+    fn move_dolly(&mut self, dt: f32, forward: Vector3<f32>, right: Vector3<f32>, input: &CameraInput) {
+        let world_up = -self.up;
+        let [move_right, move_up, move_forward] = input.movement;
+
+        // Move: left stick and movement keys (forward/back + strafe)
+        let mut move_dir = forward * move_forward + right * move_right;
         let len2 = move_dir.magnitude2();
         if len2 > 1.0 { move_dir /= len2.sqrt(); }
         let controller_move = move_dir * (self.move_speed * dt);
 
-        // Vertical via D-pad
-        let mut vertical_move = Vector3::new(0.0, 0.0, 0.0);
-        if self.dpadup { vertical_move += world_up * (self.move_speed * dt); }
-        if self.dpaddown { vertical_move -= world_up * (self.move_speed * dt); }
-
-        // Mouse pan with middle button: right/left and up/down in world space
-        let mut pan_move = Vector3::new(0.0, 0.0, 0.0);
-        if self.mouse_middle_down {
-            let x_sign = if self.invert_pan_x { -1.0 } else { 1.0 };
-            pan_move += right * ((self.mouse_dx * x_sign) * self.mouse_pan_sensitivity);
-            let y_sign = if self.invert_pan_y { -1.0 } else { 1.0 };
-            pan_move += world_up * ((self.mouse_dy * y_sign) * self.mouse_pan_sensitivity);
-        }
+        // Vertical via D-pad and keys
+        let vertical_move = world_up * (move_up * self.move_speed * dt);
+
+        // Mouse pan: right/left and up/down in world space
+        let pan_move = right * input.pan[0] + world_up * input.pan[1];
 
-        // Mouse wheel dolly along forward
-        let dolly_move = forward * (self.scroll * self.scroll_speed);
+        // Mouse wheel dolly along forward, or zoom where moving closer wouldn't show
+        let mut dolly_move = forward * (input.zoom * self.scroll_speed);
+        if self.projection == Projection::Orthograpic {
+            self.zoom_ortho(input.zoom * self.zoom_speed);
+            dolly_move = Vector3::new(0.0, 0.0, 0.0);
+        }
 
         // Apply combined movement
         self.eye += controller_move + vertical_move + pan_move + dolly_move;
 
         // Keep target ahead of eye
         self.target = self.eye + forward * self.focus_distance;
+    }
+
+    // The look input has already turned the view, the eye follows around the pivot
+    fn move_orbit(&mut self, dt: f32, forward: Vector3<f32>, right: Vector3<f32>, input: &CameraInput) {
+        let up = right.cross(forward);
+        let [move_right, move_up, move_forward] = input.movement;
+
+        // Zoom: wheel and moving forward, proportional to the distance so it never passes the
+        // pivot. Distance doesn't change an orthographic image, the view height does.
+        let zoom = input.zoom * self.zoom_speed + move_forward * dt;
+        match self.projection {
+            Projection::Perspective => self.distance = (self.distance * (-zoom).exp()).max(1e-3),
+            Projection::Orthograpic => self.zoom_ortho(zoom),
+        }
+
+        // Pan the pivot in the view plane: mouse, sideways and vertical movement
+        let mut pan = (right * move_right + up * move_up) * (self.move_speed * dt);
+        pan += right * input.pan[0] + up * input.pan[1];
+        let pan_scale = match self.projection {
+            Projection::Perspective => self.distance,
+            Projection::Orthograpic => self.ortho_height,
+        };
+        self.pivot += pan * pan_scale;
+
+        self.eye = self.pivot - forward * self.distance;
+        self.target = self.pivot;
+    }
+
+    fn zoom_ortho(&mut self, zoom: f32) {
+        self.ortho_height = (self.ortho_height * (-zoom).exp()).max(1e-3);
+    }
 
+    fn update_uniform(&mut self, queue: &wgpu::Queue) {
         let theta = self.fovy.to_radians();
         let half_height = (theta * 0.5).tan();
         let half_width = self.aspect * half_height;
@@ -279,10 +564,20 @@ impl Camera {
         let u_axis = self.up.cross(w_axis).normalize();
         let v_axis = w_axis.cross(u_axis);
 
-        let horizontal = 2.0 * half_width * self.focus_distance * u_axis;
-        let vertical = 2.0 * half_height * self.focus_distance * v_axis;
-        let lower_left_corner =
-            self.eye - (horizontal * 0.5) - (vertical * 0.5) - (self.focus_distance * w_axis);
+        // Perspective rays aim at the focus plane, orthographic ones start on the eye plane
+        let (horizontal, vertical, lower_left_corner) = match self.projection {
+            Projection::Perspective => {
+                let horizontal = 2.0 * half_width * self.focus_distance * u_axis;
+                let vertical = 2.0 * half_height * self.focus_distance * v_axis;
+                let corner = self.eye - (horizontal * 0.5) - (vertical * 0.5) - (self.focus_distance * w_axis);
+                (horizontal, vertical, corner)
+            }
+            Projection::Orthograpic => {
+                let horizontal = self.ortho_height * self.aspect * u_axis;
+                let vertical = self.ortho_height * v_axis;
+                (horizontal, vertical, self.eye - (horizontal * 0.5) - (vertical * 0.5))
+            }
+        };
 
         self.uniform.w_axis = w_axis.into();
         self.uniform.u_axis = u_axis.into();
@@ -292,13 +587,70 @@ impl Camera {
         self.uniform.horizontal = horizontal.into();
         self.uniform.vertical = vertical.into();
         self.uniform.lower_left_corner = lower_left_corner.into();
-        self.uniform.projection = self.projection.clone() as u32;
+        self.uniform.projection = self.projection as u32;
+
+        // Far enough to see all of the bounds from the eye, with the near plane as far out as
+        // that allows for depth precision
+        if let Some((center, radius)) = self.bounds {
+            let distance = (center - self.eye).magnitude();
+            self.zfar = distance + 2.0 * radius;
+            self.znear = (distance - 2.0 * radius).max(self.zfar * 1e-4);
+        }
+
+        let view_proj = self.view_projection(u_axis, v_axis, w_axis);
+        self.uniform.view_proj = view_proj.into();
+        self.uniform.inv_view_proj = view_proj.invert().unwrap_or(Matrix4::identity()).into();
 
         queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    // Camera space x follows u, y is screen up (-v, the rays step down the screen along v)
+    // and the camera looks down -z, so raster and ray traced images line up.
+    fn view_projection(&self, u_axis: Vector3<f32>, v_axis: Vector3<f32>, w_axis: Vector3<f32>) -> Matrix4<f32> {
+        let eye = self.eye;
+        #[rustfmt::skip]
+        let view = Matrix4::new(
+            u_axis.x, -v_axis.x, w_axis.x, 0.0,
+            u_axis.y, -v_axis.y, w_axis.y, 0.0,
+            u_axis.z, -v_axis.z, w_axis.z, 0.0,
+            -u_axis.dot(eye), v_axis.dot(eye), -w_axis.dot(eye), 1.0,
+        );
+
+        let proj = match self.projection {
+            Projection::Perspective => {
+                cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar)
+            }
+            Projection::Orthograpic => {
+                let h = self.ortho_height * 0.5;
+                let w = h * self.aspect;
+                cgmath::ortho(-w, w, -h, h, self.znear, self.zfar)
+            }
+        };
 
-        // Reset one-shot mouse deltas
-        self.mouse_dx = 0.0;
-        self.mouse_dy = 0.0;
-        self.scroll = 0.0;
+        OPENGL_TO_WGPU_MATRIX * proj * view
     }
 }
+
+impl Transition {
+    // Turns along the shortest rotation between the two orientations, zooms geometrically
+    fn view_at(&self, t: f32) -> CameraView {
+        let from = orientation(&self.from);
+        let mut to = orientation(&self.to);
+        if from.dot(to) < 0.0 {
+            to = -to;
+        }
+        let rotation = Matrix3::from(from.slerp(to, t));
+        CameraView {
+            pivot: self.from.pivot + (self.to.pivot - self.from.pivot) * t,
+            forward: -rotation.z,
+            up: rotation.y,
+            distance: self.from.distance * (self.to.distance / self.from.distance).powf(t),
+        }
+    }
+}
+
+fn orientation(view: &CameraView) -> Quaternion<f32> {
+    let forward = view.forward.normalize();
+    let right = forward.cross(view.up).normalize();
+    Quaternion::from(Matrix3::from_cols(right, right.cross(forward), -forward))
+}
@@ -0,0 +1,358 @@
+use std::f32::consts::PI;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+pub const DEFAULT_ENVIRONMENT_PATH: &str = "assets/environment.hdr";
+
+// Maps are downsampled to at most this many texels, finer maps cost memory without changing
+// the lighting visibly
+const MAX_ENVIRONMENT_TEXELS: u64 = 2048 * 1024;
+
+/// Equirectangular radiance map in linear RGB. Row 0 is the zenith, the centre column looks
+/// down -z; the shaders use y as up.
+pub struct EnvironmentMap {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 4]>,
+}
+
+/// Placement and strength of the environment light.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnvironmentSettings {
+    pub intensity: f32,
+    pub rotation: f32, // degrees around the up axis
+}
+
+impl Default for EnvironmentSettings {
+    fn default() -> Self {
+        Self {
+            intensity: 1.0,
+            rotation: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct EnvironmentUniform {
+    width: u32,
+    height: u32,
+    enabled: u32,
+    intensity: f32,
+    rotation: f32, // turns
+    total: f32,    // sum of luminance * sin(theta) over all pixels, 0 disables sampling
+    _pad: [f32; 2],
+}
+
+impl EnvironmentMap {
+    /// Reads a Radiance `.hdr` file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, image::ImageError> {
+        Ok(EnvironmentMap::from_image(image::open(path)?))
+    }
+
+    pub fn from_memory(bytes: &[u8]) -> Result<Self, image::ImageError> {
+        Ok(EnvironmentMap::from_image(image::load_from_memory(bytes)?))
+    }
+
+    pub fn from_image(image: image::DynamicImage) -> Self {
+        let image = image.into_rgba32f();
+        EnvironmentMap {
+            width: image.width(),
+            height: image.height(),
+            pixels: image.pixels().map(|p| p.0).collect(),
+        }
+    }
+
+    /// Whether the texture and the sampling distribution fit the device limits and the texel
+    /// budget.
+    pub fn fits(&self, limits: &wgpu::Limits) -> bool {
+        let texels = self.width as u64 * self.height as u64;
+        let cdf_size = (self.height as u64 + texels) * std::mem::size_of::<f32>() as u64;
+        self.width <= limits.max_texture_dimension_2d
+            && self.height <= limits.max_texture_dimension_2d
+            && texels <= MAX_ENVIRONMENT_TEXELS
+            && cdf_size <= limits.max_storage_buffer_binding_size as u64
+    }
+
+    /// Box filters the map to half its size, for maps that don't fit the device.
+    pub fn downsample(&self) -> Self {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0.0; 4];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let sx = (2 * x + dx).min(self.width - 1);
+                    let sy = (2 * y + dy).min(self.height - 1);
+                    let p = self.pixels[(sy * self.width + sx) as usize];
+                    for (s, c) in sum.iter_mut().zip(p) {
+                        *s += c * 0.25;
+                    }
+                }
+                pixels.push(sum);
+            }
+        }
+        EnvironmentMap { width, height, pixels }
+    }
+
+    /// Tabulated distribution for importance sampling by luminance, weighted by the solid
+    /// angle of each row. Returns the marginal CDF over rows followed by one conditional CDF
+    /// per row, and the unnormalized total.
+    pub fn distribution(&self) -> (Vec<f32>, f32) {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut cdf = vec![0.0; height + width * height];
+        let mut row_sums = vec![0.0; height];
+        for y in 0..height {
+            let sin_theta = ((y as f32 + 0.5) / height as f32 * PI).sin();
+            let row = &mut cdf[height + y * width..height + (y + 1) * width];
+            let mut sum = 0.0;
+            for (x, c) in row.iter_mut().enumerate() {
+                sum += luminance(self.pixels[y * width + x]) * sin_theta;
+                *c = sum;
+            }
+            normalize_cdf(row, sum);
+            row_sums[y] = sum;
+        }
+
+        let mut sum = 0.0;
+        for (c, row_sum) in cdf[..height].iter_mut().zip(&row_sums) {
+            sum += row_sum;
+            *c = sum;
+        }
+        normalize_cdf(&mut cdf[..height], sum);
+        (cdf, sum)
+    }
+}
+
+// Black rows and maps fall back to uniform sampling
+fn normalize_cdf(cdf: &mut [f32], sum: f32) {
+    let count = cdf.len() as f32;
+    for (i, c) in cdf.iter_mut().enumerate() {
+        *c = if sum > 0.0 { *c / sum } else { (i + 1) as f32 / count };
+    }
+    if let Some(last) = cdf.last_mut() {
+        *last = 1.0;
+    }
+}
+
+fn luminance(rgb: [f32; 4]) -> f32 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+/// GPU side of an environment light: the map, its sampling distribution and a uniform, in a
+/// bind group of their own that the path tracers bind as group 1 (see environment.wgsl).
+pub struct Environment {
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    uniform_buffer: wgpu::Buffer,
+    uniform: EnvironmentUniform,
+    settings: EnvironmentSettings,
+}
+
+impl Environment {
+    /// Starts without a map, the shaders fall back to their own background.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("environment bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("environment buffer"),
+            size: std::mem::size_of::<EnvironmentUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let placeholder = EnvironmentMap {
+            width: 1,
+            height: 1,
+            pixels: vec![[0.0; 4]],
+        };
+        let bind_group = Environment::create_bind_group(
+            device,
+            queue,
+            &bind_group_layout,
+            &uniform_buffer,
+            &placeholder,
+            &[1.0, 1.0],
+        );
+
+        let mut environment = Environment {
+            bind_group_layout,
+            bind_group,
+            uniform_buffer,
+            uniform: EnvironmentUniform {
+                width: 1,
+                height: 1,
+                enabled: 0,
+                intensity: 1.0,
+                rotation: 0.0,
+                total: 0.0,
+                _pad: [0.0; 2],
+            },
+            settings: EnvironmentSettings::default(),
+        };
+        environment.set_settings(queue, EnvironmentSettings::default());
+        environment
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.uniform.enabled != 0
+    }
+
+    /// Uploads a new map, or goes back to the fallback background with `None`.
+    pub fn set_map(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, map: Option<&EnvironmentMap>) {
+        let Some(map) = map else {
+            self.uniform.enabled = 0;
+            self.uniform.total = 0.0;
+            queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+            return;
+        };
+
+        let limits = device.limits();
+        let mut downsampled: Option<EnvironmentMap> = None;
+        let map = loop {
+            let current = downsampled.as_ref().unwrap_or(map);
+            if current.fits(&limits) || current.width * current.height == 1 {
+                break current;
+            }
+            downsampled = Some(current.downsample());
+        };
+
+        let (cdf, total) = map.distribution();
+        self.bind_group = Environment::create_bind_group(device, queue, &self.bind_group_layout, &self.uniform_buffer, map, &cdf);
+        self.uniform.width = map.width;
+        self.uniform.height = map.height;
+        self.uniform.enabled = 1;
+        self.uniform.total = total;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    pub fn settings(&self) -> EnvironmentSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: EnvironmentSettings) {
+        self.settings = settings;
+        self.uniform.intensity = settings.intensity.max(0.0);
+        self.uniform.rotation = settings.rotation / 360.0;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        map: &EnvironmentMap,
+        cdf: &[f32],
+    ) -> wgpu::BindGroup {
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Environment Map"),
+                size: wgpu::Extent3d {
+                    width: map.width,
+                    height: map.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            bytemuck::cast_slice(&map.pixels),
+        );
+        let texture_view = texture.create_view(&Default::default());
+
+        let cdf_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("environment cdf buffer"),
+            contents: bytemuck::cast_slice(cdf),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("environment bind group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: cdf_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(width: u32, height: u32) -> EnvironmentMap {
+        EnvironmentMap { width, height, pixels: vec![[1.0; 4]; (width * height) as usize] }
+    }
+
+    #[test]
+    fn large_maps_dont_fit_the_default_limits() {
+        let limits = wgpu::Limits::default();
+        assert!(map(2048, 1024).fits(&limits));
+        // Within the texture limit, but the CDF alone is larger than a storage binding
+        let large = EnvironmentMap { width: 8192, height: 4096, pixels: Vec::new() };
+        assert!(large.width <= limits.max_texture_dimension_2d);
+        assert!(!large.fits(&limits));
+        assert!(!EnvironmentMap { width: 4096, height: 2048, pixels: Vec::new() }.fits(&limits));
+    }
+
+    #[test]
+    fn downsampling_keeps_the_radiance() {
+        let half = map(6, 4).downsample();
+        assert_eq!((half.width, half.height), (3, 2));
+        assert!(half.pixels.iter().all(|p| *p == [1.0; 4]));
+    }
+}
@@ -7,6 +7,12 @@ pub struct FPSCounter {
     last_second_frames: VecDeque<Instant>,
 }
 
+impl Default for FPSCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FPSCounter {
     pub fn new() -> Self {
         FPSCounter {
@@ -21,7 +27,7 @@ impl FPSCounter {
         while self
             .last_second_frames
             .front()
-            .map_or(false, |t| *t < a_second_ago)
+            .is_some_and(|t| *t < a_second_ago)
         {
             self.last_second_frames.pop_front();
         }
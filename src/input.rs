@@ -0,0 +1,405 @@
+use std::collections::{BTreeMap, HashMap};
+
+use gilrs::{Axis, Button, Event, EventType, Gilrs};
+use serde::{Deserialize, Serialize};
+use winit::event::MouseButton;
+use winit::keyboard::PhysicalKey;
+
+pub const DEFAULT_BINDINGS_PATH: &str = "assets/input.ron";
+
+/// Something the viewer does in response to input. Moving, looking and panning last as long
+/// as a bound input is held, the other actions fire once when it is pressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Look, // mouse motion turns the view
+    Pan,  // mouse motion moves the view sideways
+    Quit,
+    ToggleCameraMode,
+    ToggleProjection,
+    ResetView,
+    ViewAnterior,
+    ViewPosterior,
+    ViewLeft,
+    ViewRight,
+    ViewSuperior,
+    ViewInferior,
+    Turntable,      // starts or stops a turntable
+    Record,         // starts recording a camera path or stops and saves it
+    PlayCameraPath, // plays the saved camera path
+    SaveSession,
+    LoadSession,
+    LoadEnvironment,       // lights the volume and the scene with the HDR map in the assets
+    CycleMedicalMode,      // MIP, DVR, slices and the cinematic path tracer
+    ToggleLabel(u32),      // shows or hides one structure of the segmentation
+    CycleBlendMode,        // alpha, additive and checkerboard fusion
+    ShiftFusion([i32; 3]), // moves the fusion volume by whole mm along the patient axes
+    ShiftIsoValue(i32),    // extracts the surface again at a higher or lower value (HU)
+    CycleSurfaceLabel,     // extracts the surface of the next structure instead
+    ExportSurface,         // saves the extracted surface as STL, or downloads it in the browser
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum MouseBinding {
+    Left,
+    Right,
+    Middle,
+    Back,
+    Forward,
+}
+
+/// Sensitivity and direction of the mouse and the gamepad sticks.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputSettings {
+    pub mouse_look_sensitivity: f32, // radians per pixel
+    pub mouse_pan_sensitivity: f32,  // units per pixel, per unit of distance when orbiting
+    pub stick_look_sensitivity: f32, // radians per second at full deflection
+    pub stick_deadzone: f32,
+    pub invert_look_x: bool, // mouse
+    pub invert_look_y: bool,
+    pub invert_pan_x: bool,
+    pub invert_pan_y: bool,
+    pub invert_stick_x: bool, // right stick
+    pub invert_stick_y: bool,
+}
+
+/// Which keys, mouse buttons and gamepad buttons trigger which action, stored as RON like the
+/// scene files. Keys are named by winit's `KeyCode`, their position on a US keyboard ("KeyW",
+/// "ArrowUp", "Digit1"), so movement stays on the same keys with any layout. Gamepad buttons
+/// are named by gilrs ("DPadUp", "South"). The left stick always moves and the right stick
+/// looks.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bindings {
+    pub keys: BTreeMap<String, Action>,
+    pub mouse: BTreeMap<MouseBinding, Action>,
+    pub gamepad: BTreeMap<String, Action>,
+    pub settings: InputSettings,
+}
+
+/// What the held inputs ask of the camera for one frame, see Camera::update().
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CameraInput {
+    pub look: [f32; 2],      // yaw and pitch in radians, from the mouse
+    pub look_rate: [f32; 2], // yaw and pitch in radians per second, from the right stick
+    pub movement: [f32; 3],  // right, up and forward, -1 to 1 of the move speed
+    pub pan: [f32; 2],       // right and up, from the mouse
+    pub zoom: f32,           // wheel units
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Source {
+    Key(String),
+    Mouse(MouseBinding),
+    Gamepad(String),
+}
+
+/// Maps window and gamepad events to actions through the bindings, and collects the camera
+/// input between frames.
+pub struct Input {
+    pub bindings: Bindings,
+    gilrs: Option<Gilrs>,
+    held: HashMap<Source, Action>,
+    left_stick: [f32; 2],
+    right_stick: [f32; 2],
+    mouse_delta: [f32; 2],
+    scroll: f32,
+}
+
+impl Action {
+    pub fn is_held(self) -> bool {
+        use Action::*;
+        matches!(self, MoveForward | MoveBack | MoveLeft | MoveRight | MoveUp | MoveDown | Look | Pan)
+    }
+}
+
+impl Default for InputSettings {
+    fn default() -> Self {
+        Self {
+            mouse_look_sensitivity: 0.0035,
+            mouse_pan_sensitivity: 0.003,
+            stick_look_sensitivity: 0.8,
+            stick_deadzone: 0.12,
+            invert_look_x: true,
+            invert_look_y: true,
+            invert_pan_x: true,
+            invert_pan_y: true,
+            invert_stick_x: false,
+            invert_stick_y: false,
+        }
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let keys = [
+            ("KeyW", Action::MoveForward),
+            ("KeyS", Action::MoveBack),
+            ("KeyA", Action::MoveLeft),
+            ("KeyD", Action::MoveRight),
+            ("KeyE", Action::MoveUp),
+            ("KeyC", Action::MoveDown),
+            ("ArrowUp", Action::MoveForward),
+            ("ArrowDown", Action::MoveBack),
+            ("ArrowLeft", Action::MoveLeft),
+            ("ArrowRight", Action::MoveRight),
+            ("PageUp", Action::MoveUp),
+            ("PageDown", Action::MoveDown),
+            ("KeyQ", Action::Quit),
+            ("Escape", Action::Quit),
+            ("KeyO", Action::ToggleCameraMode),
+            ("KeyP", Action::ToggleProjection),
+            ("KeyR", Action::ResetView),
+            ("Digit1", Action::ViewAnterior),
+            ("Digit2", Action::ViewPosterior),
+            ("Digit3", Action::ViewLeft),
+            ("Digit4", Action::ViewRight),
+            ("Digit5", Action::ViewSuperior),
+            ("Digit6", Action::ViewInferior),
+            ("KeyT", Action::Turntable),
+            ("KeyK", Action::Record),
+            ("KeyL", Action::PlayCameraPath),
+            ("F5", Action::SaveSession),
+            ("F9", Action::LoadSession),
+            ("KeyH", Action::LoadEnvironment),
+            ("KeyM", Action::CycleMedicalMode),
+            ("Numpad1", Action::ToggleLabel(1)),
+            ("Numpad2", Action::ToggleLabel(2)),
+            ("Numpad3", Action::ToggleLabel(3)),
+            ("Numpad4", Action::ToggleLabel(4)),
+            ("Numpad5", Action::ToggleLabel(5)),
+            ("Numpad6", Action::ToggleLabel(6)),
+            ("Numpad7", Action::ToggleLabel(7)),
+            ("Numpad8", Action::ToggleLabel(8)),
+            ("Numpad9", Action::ToggleLabel(9)),
+            ("KeyB", Action::CycleBlendMode),
+            ("BracketLeft", Action::ShiftFusion([-1, 0, 0])),
+            ("BracketRight", Action::ShiftFusion([1, 0, 0])),
+            ("Semicolon", Action::ShiftFusion([0, -1, 0])),
+            ("Quote", Action::ShiftFusion([0, 1, 0])),
+            ("Comma", Action::ShiftFusion([0, 0, -1])),
+            ("Period", Action::ShiftFusion([0, 0, 1])),
+            ("Equal", Action::ShiftIsoValue(50)),
+            ("Minus", Action::ShiftIsoValue(-50)),
+            ("KeyN", Action::CycleSurfaceLabel),
+            ("KeyX", Action::ExportSurface),
+        ];
+        let mouse = [
+            (MouseBinding::Left, Action::Look),
+            (MouseBinding::Right, Action::Look),
+            (MouseBinding::Middle, Action::Pan),
+        ];
+        let gamepad = [("DPadUp", Action::MoveUp), ("DPadDown", Action::MoveDown)];
+
+        Self {
+            keys: keys.into_iter().map(|(key, action)| (key.to_string(), action)).collect(),
+            mouse: mouse.into_iter().collect(),
+            gamepad: gamepad.into_iter().map(|(button, action)| (button.to_string(), action)).collect(),
+            settings: InputSettings::default(),
+        }
+    }
+}
+
+impl Input {
+    pub fn new(bindings: Bindings) -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => {
+                for (_id, gamepad) in gilrs.gamepads() {
+                    println!("{} is {:?}", gamepad.name(), gamepad.power_info());
+                }
+                Some(gilrs)
+            }
+            Err(e) => {
+                println!("no gamepad input: {e}");
+                None
+            }
+        };
+
+        Self {
+            bindings,
+            gilrs,
+            held: HashMap::new(),
+            left_stick: [0.0; 2],
+            right_stick: [0.0; 2],
+            mouse_delta: [0.0; 2],
+            scroll: 0.0,
+        }
+    }
+
+    pub fn is_held(&self, action: Action) -> bool {
+        self.held.values().any(|&held| held == action)
+    }
+
+    /// The action to perform for a key, if it fires on this press. Key repeats fire nothing.
+    pub fn key(&mut self, key: PhysicalKey, pressed: bool, repeat: bool) -> Option<Action> {
+        let name = match key {
+            PhysicalKey::Code(code) => format!("{code:?}"),
+            PhysicalKey::Unidentified(_) => return None,
+        };
+        let action = self.bindings.keys.get(&name).copied();
+        self.press(Source::Key(name), action, pressed).filter(|_| !repeat)
+    }
+
+    pub fn mouse_button(&mut self, button: MouseButton, pressed: bool) -> Option<Action> {
+        let button = match button {
+            MouseButton::Left => MouseBinding::Left,
+            MouseButton::Right => MouseBinding::Right,
+            MouseButton::Middle => MouseBinding::Middle,
+            MouseButton::Back => MouseBinding::Back,
+            MouseButton::Forward => MouseBinding::Forward,
+            MouseButton::Other(_) => return None,
+        };
+        let action = self.bindings.mouse.get(&button).copied();
+        self.press(Source::Mouse(button), action, pressed)
+    }
+
+    pub fn mouse_motion(&mut self, dx: f32, dy: f32) {
+        self.mouse_delta[0] += dx;
+        self.mouse_delta[1] += dy;
+    }
+
+    pub fn mouse_wheel(&mut self, delta: f32) {
+        self.scroll += delta;
+    }
+
+    /// Forgets held inputs whose release the window won't see, e.g. when it loses focus.
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+
+    /// Reads the gamepad events since the last call and returns the actions they fire.
+    pub fn poll_gamepad(&mut self) -> Vec<Action> {
+        let mut actions = Vec::new();
+        while let Some(Event { event, .. }) = self.gilrs.as_mut().and_then(Gilrs::next_event) {
+            match event {
+                EventType::ButtonPressed(button, _) => actions.extend(self.gamepad_button(button, true)),
+                EventType::ButtonReleased(button, _) => {
+                    self.gamepad_button(button, false);
+                }
+                EventType::AxisChanged(Axis::LeftStickX, value, _) => self.left_stick[0] = value,
+                EventType::AxisChanged(Axis::LeftStickY, value, _) => self.left_stick[1] = value,
+                EventType::AxisChanged(Axis::RightStickX, value, _) => self.right_stick[0] = value,
+                EventType::AxisChanged(Axis::RightStickY, value, _) => self.right_stick[1] = value,
+                EventType::Disconnected => {
+                    self.left_stick = [0.0; 2];
+                    self.right_stick = [0.0; 2];
+                }
+                _ => {}
+            }
+        }
+        actions
+    }
+
+    /// The camera input collected since the last call, with the settings applied.
+    pub fn camera_input(&mut self) -> CameraInput {
+        let settings = &self.bindings.settings;
+        let deadzone = |value: f32| if value.abs() < settings.stick_deadzone { 0.0 } else { value };
+        let sign = |invert: bool| if invert { -1.0 } else { 1.0 };
+        let axis = |positive, negative| {
+            (self.is_held(positive) as i32 - self.is_held(negative) as i32) as f32
+        };
+
+        let [dx, dy] = self.mouse_delta;
+        let mut input = CameraInput {
+            look_rate: [
+                deadzone(self.right_stick[0]) * sign(settings.invert_stick_x) * settings.stick_look_sensitivity,
+                deadzone(self.right_stick[1]) * sign(settings.invert_stick_y) * settings.stick_look_sensitivity,
+            ],
+            movement: [
+                (deadzone(self.left_stick[0]) + axis(Action::MoveRight, Action::MoveLeft)).clamp(-1.0, 1.0),
+                axis(Action::MoveUp, Action::MoveDown),
+                (-deadzone(self.left_stick[1]) + axis(Action::MoveForward, Action::MoveBack)).clamp(-1.0, 1.0),
+            ],
+            zoom: self.scroll,
+            ..Default::default()
+        };
+        if self.is_held(Action::Look) {
+            input.look = [
+                dx * sign(settings.invert_look_x) * settings.mouse_look_sensitivity,
+                dy * sign(settings.invert_look_y) * settings.mouse_look_sensitivity,
+            ];
+        }
+        if self.is_held(Action::Pan) {
+            input.pan = [
+                dx * sign(settings.invert_pan_x) * settings.mouse_pan_sensitivity,
+                dy * sign(settings.invert_pan_y) * settings.mouse_pan_sensitivity,
+            ];
+        }
+
+        self.mouse_delta = [0.0; 2];
+        self.scroll = 0.0;
+        input
+    }
+
+    fn gamepad_button(&mut self, button: Button, pressed: bool) -> Option<Action> {
+        let name = format!("{button:?}");
+        let action = self.bindings.gamepad.get(&name).copied();
+        self.press(Source::Gamepad(name), action, pressed)
+    }
+
+    // Held actions are tracked per input, so releasing one of two bound keys keeps moving
+    fn press(&mut self, source: Source, action: Option<Action>, pressed: bool) -> Option<Action> {
+        if !pressed {
+            self.held.remove(&source);
+            return None;
+        }
+        let action = action?;
+        if action.is_held() {
+            self.held.insert(source, action);
+            return None;
+        }
+        Some(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::keyboard::KeyCode;
+
+    use super::*;
+    use crate::ron_file;
+
+    #[test]
+    fn bindings_survive_a_ron_round_trip() {
+        let mut bindings = Bindings::default();
+        bindings.keys.insert("KeyZ".to_string(), Action::ResetView);
+        bindings.settings.invert_look_y = false;
+        bindings.settings.invert_stick_y = true;
+        let restored: Bindings = ron::from_str(&ron_file::to_string(&bindings)).unwrap();
+        assert_eq!(restored, bindings);
+    }
+
+    #[test]
+    fn keys_map_to_their_action() {
+        let mut input = Input::new(Bindings::default());
+        let digit1 = PhysicalKey::Code(KeyCode::Digit1);
+        assert_eq!(input.key(digit1, true, false), Some(Action::ViewAnterior));
+        assert_eq!(input.key(digit1, true, true), None);
+
+        // Movement is held rather than fired, until the key is released
+        let w = PhysicalKey::Code(KeyCode::KeyW);
+        assert_eq!(input.key(w, true, false), None);
+        assert!(input.is_held(Action::MoveForward));
+        assert_eq!(input.camera_input().movement, [0.0, 0.0, 1.0]);
+        input.key(w, false, false);
+        assert!(!input.is_held(Action::MoveForward));
+    }
+
+    #[test]
+    fn mouse_look_is_inverted_by_default_and_the_stick_is_not() {
+        let mut input = Input::new(Bindings::default());
+        input.mouse_button(MouseButton::Left, true);
+        input.mouse_motion(10.0, 20.0);
+        input.right_stick = [0.5, 0.5];
+        let camera_input = input.camera_input();
+        let settings = input.bindings.settings;
+        assert_eq!(camera_input.look, [-10.0, -20.0].map(|d| d * settings.mouse_look_sensitivity));
+        assert_eq!(camera_input.look_rate, [0.5, 0.5].map(|d| d * settings.stick_look_sensitivity));
+    }
+}
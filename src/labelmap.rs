@@ -0,0 +1,105 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Display style of each label in a segmentation volume. Label 0 is background.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LabelMap {
+    styles: Vec<LabelStyle>,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Serialize, Deserialize)]
+pub struct LabelStyle {
+    pub color: [f32; 4], // rgb + opacity
+    #[serde(serialize_with = "serialize_flag", deserialize_with = "deserialize_flag")]
+    pub visible: u32,
+    #[serde(skip)]
+    _pad: [u32; 3],
+}
+
+// The shader wants a u32, the file a bool
+fn serialize_flag<S: Serializer>(flag: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bool(*flag != 0)
+}
+
+fn deserialize_flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    bool::deserialize(deserializer).map(u32::from)
+}
+
+const PALETTE: [[f32; 3]; 10] = [
+    [0.90, 0.30, 0.25],
+    [0.30, 0.70, 0.90],
+    [0.95, 0.80, 0.25],
+    [0.45, 0.80, 0.35],
+    [0.70, 0.45, 0.85],
+    [0.95, 0.55, 0.20],
+    [0.30, 0.85, 0.75],
+    [0.90, 0.45, 0.70],
+    [0.60, 0.60, 0.95],
+    [0.80, 0.70, 0.55],
+];
+
+impl LabelMap {
+    pub fn new(max_label: u32) -> Self {
+        let styles = (0..=max_label)
+            .map(|label| {
+                let [r, g, b] = PALETTE[(label as usize + PALETTE.len() - 1) % PALETTE.len()];
+                LabelStyle {
+                    color: [r, g, b, 0.5],
+                    visible: (label != 0) as u32,
+                    _pad: [0; 3],
+                }
+            })
+            .collect();
+        Self { styles }
+    }
+
+    pub fn len(&self) -> usize {
+        self.styles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.styles.is_empty()
+    }
+
+    pub fn styles(&self) -> &[LabelStyle] {
+        &self.styles
+    }
+
+    pub fn is_visible(&self, label: u32) -> bool {
+        self.styles
+            .get(label as usize)
+            .is_some_and(|s| s.visible != 0)
+    }
+
+    pub fn set_visible(&mut self, label: u32, visible: bool) {
+        if let Some(style) = self.styles.get_mut(label as usize) {
+            style.visible = visible as u32;
+        }
+    }
+
+    pub fn toggle_visible(&mut self, label: u32) {
+        let visible = self.is_visible(label);
+        self.set_visible(label, !visible);
+    }
+
+    pub fn set_color(&mut self, label: u32, rgb: [f32; 3]) {
+        if let Some(style) = self.styles.get_mut(label as usize) {
+            style.color = [rgb[0], rgb[1], rgb[2], style.color[3]];
+        }
+    }
+
+    /// Takes over the styles of the labels both maps have, e.g. from a session saved with
+    /// another segmentation.
+    pub fn restore(&mut self, saved: &LabelMap) {
+        for (style, saved) in self.styles.iter_mut().zip(&saved.styles) {
+            *style = *saved;
+        }
+    }
+
+    pub fn set_opacity(&mut self, label: u32, opacity: f32) {
+        if let Some(style) = self.styles.get_mut(label as usize) {
+            style.color[3] = opacity.clamp(0.0, 1.0);
+        }
+    }
+}
@@ -3,5 +3,15 @@ pub mod renderer;
 pub mod pipelines;
 pub mod fpscounter;
 pub mod camera;
+pub mod animation;
 pub mod quad;
 pub mod vertex;
+pub mod volume;
+pub mod transfer_function;
+pub mod labelmap;
+pub mod mesh;
+pub mod scene;
+pub mod environment;
+pub mod session;
+pub mod ron_file;
+pub mod input;
@@ -0,0 +1,371 @@
+use bytemuck::Zeroable;
+use cgmath::Vector3;
+
+use crate::mesh::TriangleMesh;
+
+const MAX_LEAF_TRIANGLES: usize = 4;
+const MAX_DEPTH: u32 = 30; // the shader's traversal stack holds 32 nodes
+const BINS: usize = 12;
+
+/// Node of a flattened bounding volume hierarchy. Interior nodes (`count == 0`) have their
+/// children at `left_or_first` and `left_or_first + 1`, leaves hold `count` triangles
+/// starting at `left_or_first`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BvhNode {
+    pub min: [f32; 3],
+    pub left_or_first: u32,
+    pub max: [f32; 3],
+    pub count: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BvhTriangle {
+    pub v0: [f32; 3],
+    pub material: u32,
+    pub v1: [f32; 3],
+    _pad0: f32,
+    pub v2: [f32; 3],
+    _pad1: f32,
+    pub n0: [f32; 3],
+    _pad2: f32,
+    pub n1: [f32; 3],
+    _pad3: f32,
+    pub n2: [f32; 3],
+    _pad4: f32,
+}
+
+/// Binned SAH hierarchy over the triangles of several meshes, laid out for the ray tracer.
+#[derive(Default)]
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    pub triangles: Vec<BvhTriangle>,
+}
+
+#[derive(Clone, Copy)]
+struct Aabb {
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+}
+
+impl Aabb {
+    fn empty() -> Self {
+        Aabb {
+            min: Vector3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Vector3::new(f32::MIN, f32::MIN, f32::MIN),
+        }
+    }
+
+    fn grow(&mut self, p: Vector3<f32>) {
+        self.min = Vector3::new(self.min.x.min(p.x), self.min.y.min(p.y), self.min.z.min(p.z));
+        self.max = Vector3::new(self.max.x.max(p.x), self.max.y.max(p.y), self.max.z.max(p.z));
+    }
+
+    fn merge(&mut self, other: &Aabb) {
+        if other.min.x > other.max.x {
+            return;
+        }
+        self.grow(other.min);
+        self.grow(other.max);
+    }
+
+    fn area(&self) -> f32 {
+        let e = self.max - self.min;
+        if e.x < 0.0 {
+            return 0.0;
+        }
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+}
+
+impl Bvh {
+    /// `meshes` pairs each mesh with the material index its triangles are shaded with.
+    pub fn build<'a>(meshes: impl IntoIterator<Item = (&'a TriangleMesh, u32)>) -> Self {
+        let mut triangles = Vec::new();
+        for (mesh, material) in meshes {
+            for t in mesh.indices.chunks_exact(3) {
+                let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[t[i] as usize]);
+                triangles.push(BvhTriangle {
+                    v0: a.position,
+                    material,
+                    v1: b.position,
+                    _pad0: 0.0,
+                    v2: c.position,
+                    _pad1: 0.0,
+                    n0: a.normal,
+                    _pad2: 0.0,
+                    n1: b.normal,
+                    _pad3: 0.0,
+                    n2: c.normal,
+                    _pad4: 0.0,
+                });
+            }
+        }
+        if triangles.is_empty() {
+            return Bvh::default();
+        }
+
+        let bounds: Vec<Aabb> = triangles
+            .iter()
+            .map(|t| {
+                let mut b = Aabb::empty();
+                for v in [t.v0, t.v1, t.v2] {
+                    b.grow(Vector3::from(v));
+                }
+                b
+            })
+            .collect();
+        let centroids: Vec<Vector3<f32>> = bounds.iter().map(|b| (b.min + b.max) * 0.5).collect();
+
+        let mut builder = Builder {
+            nodes: Vec::with_capacity(2 * triangles.len()),
+            order: (0..triangles.len()).collect(),
+            bounds,
+            centroids,
+        };
+        builder.nodes.push(BvhNode::zeroed());
+        builder.subdivide(0, 0, triangles.len(), 0);
+
+        let triangles = builder.order.iter().map(|i| triangles[*i]).collect();
+        Bvh { nodes: builder.nodes, triangles }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    /// Storage buffers can't be empty; an empty hierarchy is a root the rays never hit.
+    pub fn gpu_nodes(&self) -> Vec<BvhNode> {
+        if self.nodes.is_empty() {
+            return vec![BvhNode { min: [1.0; 3], left_or_first: 0, max: [-1.0; 3], count: 1 }];
+        }
+        self.nodes.clone()
+    }
+
+    pub fn gpu_triangles(&self) -> Vec<BvhTriangle> {
+        if self.triangles.is_empty() {
+            return vec![BvhTriangle::zeroed()];
+        }
+        self.triangles.clone()
+    }
+}
+
+struct Builder {
+    nodes: Vec<BvhNode>,
+    order: Vec<usize>, // triangle indices, partitioned in place
+    bounds: Vec<Aabb>,
+    centroids: Vec<Vector3<f32>>,
+}
+
+impl Builder {
+    fn subdivide(&mut self, node: usize, first: usize, count: usize, depth: u32) {
+        let mut bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for i in &self.order[first..first + count] {
+            bounds.merge(&self.bounds[*i]);
+            centroid_bounds.grow(self.centroids[*i]);
+        }
+        self.nodes[node] = BvhNode {
+            min: bounds.min.into(),
+            left_or_first: first as u32,
+            max: bounds.max.into(),
+            count: count as u32,
+        };
+
+        if count <= MAX_LEAF_TRIANGLES || depth >= MAX_DEPTH {
+            return;
+        }
+        let Some((axis, split)) = self.best_split(first, count, &centroid_bounds, bounds.area()) else {
+            return;
+        };
+
+        // Partition around the split plane
+        let mut left = first;
+        let mut right = first + count;
+        while left < right {
+            if self.centroids[self.order[left]][axis] < split {
+                left += 1;
+            } else {
+                right -= 1;
+                self.order.swap(left, right);
+            }
+        }
+        let left_count = left - first;
+        if left_count == 0 || left_count == count {
+            return;
+        }
+
+        let left_child = self.nodes.len();
+        self.nodes.push(BvhNode::zeroed());
+        self.nodes.push(BvhNode::zeroed());
+        self.nodes[node].left_or_first = left_child as u32;
+        self.nodes[node].count = 0;
+        self.subdivide(left_child, first, left_count, depth + 1);
+        self.subdivide(left_child + 1, left, count - left_count, depth + 1);
+    }
+
+    // Cheapest bin boundary by the surface area heuristic, None when a leaf is cheaper
+    fn best_split(&self, first: usize, count: usize, centroid_bounds: &Aabb, area: f32) -> Option<(usize, f32)> {
+        let mut best: Option<(usize, f32, f32)> = None;
+        for axis in 0..3 {
+            let (lo, hi) = (centroid_bounds.min[axis], centroid_bounds.max[axis]);
+            if hi <= lo {
+                continue;
+            }
+            let scale = BINS as f32 / (hi - lo);
+
+            let mut bin_bounds = [Aabb::empty(); BINS];
+            let mut bin_counts = [0usize; BINS];
+            for i in &self.order[first..first + count] {
+                let bin = (((self.centroids[*i][axis] - lo) * scale) as usize).min(BINS - 1);
+                bin_counts[bin] += 1;
+                bin_bounds[bin].merge(&self.bounds[*i]);
+            }
+
+            // Sweep from both sides for the areas and counts left and right of each boundary
+            let mut left_area = [0.0; BINS - 1];
+            let mut left_count = [0usize; BINS - 1];
+            let mut right_area = [0.0; BINS - 1];
+            let mut right_count = [0usize; BINS - 1];
+            let (mut left_box, mut right_box) = (Aabb::empty(), Aabb::empty());
+            let (mut left_sum, mut right_sum) = (0, 0);
+            for i in 0..BINS - 1 {
+                left_sum += bin_counts[i];
+                left_box.merge(&bin_bounds[i]);
+                left_count[i] = left_sum;
+                left_area[i] = left_box.area();
+
+                right_sum += bin_counts[BINS - 1 - i];
+                right_box.merge(&bin_bounds[BINS - 1 - i]);
+                right_count[BINS - 2 - i] = right_sum;
+                right_area[BINS - 2 - i] = right_box.area();
+            }
+
+            for i in 0..BINS - 1 {
+                let cost = left_count[i] as f32 * left_area[i] + right_count[i] as f32 * right_area[i];
+                if left_count[i] > 0 && right_count[i] > 0 && best.is_none_or(|b| cost < b.2) {
+                    best = Some((axis, lo + (i + 1) as f32 / scale, cost));
+                }
+            }
+        }
+
+        let (axis, split, cost) = best?;
+        // Always split large nodes, the leaf loop in the shader is linear
+        if cost >= count as f32 * area && count <= 4 * MAX_LEAF_TRIANGLES {
+            return None;
+        }
+        Some((axis, split))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipelines::mesh_pipeline::ModelVertex;
+
+    // Small triangles with the given corners, one mesh each so the material is the index
+    fn triangles(corners: impl IntoIterator<Item = [f32; 3]>) -> Vec<TriangleMesh> {
+        corners
+            .into_iter()
+            .map(|[x, y, z]| TriangleMesh {
+                vertices: [[x, y, z], [x + 0.1, y, z], [x, y + 0.1, z]]
+                    .map(|position| ModelVertex { position, normal: [0.0, 0.0, 1.0] })
+                    .to_vec(),
+                indices: vec![0, 1, 2],
+                colors: None,
+            })
+            .collect()
+    }
+
+    fn build(meshes: &[TriangleMesh]) -> Bvh {
+        Bvh::build(meshes.iter().zip(0..))
+    }
+
+    // Visits every node with its depth and the node it hangs from
+    fn walk(bvh: &Bvh, visit: &mut impl FnMut(&BvhNode, Option<&BvhNode>, u32)) {
+        let mut stack = vec![(0, None, 0)];
+        while let Some((index, parent, depth)) = stack.pop() {
+            let node = &bvh.nodes[index];
+            visit(node, parent.map(|p: usize| &bvh.nodes[p]), depth);
+            if node.count == 0 {
+                let left = node.left_or_first as usize;
+                stack.push((left, Some(index), depth + 1));
+                stack.push((left + 1, Some(index), depth + 1));
+            }
+        }
+    }
+
+    fn scattered() -> Vec<TriangleMesh> {
+        // Deterministic spread over a 10 unit cube
+        triangles((0..500u32).map(|i| {
+            let hash = |k: u32| (i.wrapping_mul(2654435761).wrapping_add(k * 40503) % 1000) as f32 / 100.0;
+            [hash(1), hash(2), hash(3)]
+        }))
+    }
+
+    #[test]
+    fn every_triangle_is_in_exactly_one_leaf() {
+        let meshes = scattered();
+        let bvh = build(&meshes);
+        let mut seen = vec![0; meshes.len()];
+        walk(&bvh, &mut |node, _, _| {
+            if node.count > 0 {
+                let first = node.left_or_first as usize;
+                for triangle in &bvh.triangles[first..first + node.count as usize] {
+                    seen[triangle.material as usize] += 1;
+                }
+            }
+        });
+        assert!(seen.iter().all(|count| *count == 1));
+        assert_eq!(bvh.triangles.len(), meshes.len());
+    }
+
+    #[test]
+    fn children_lie_inside_their_parent() {
+        let bvh = build(&scattered());
+        walk(&bvh, &mut |node, parent, _| {
+            if let Some(parent) = parent {
+                for axis in 0..3 {
+                    assert!(parent.min[axis] <= node.min[axis] && node.max[axis] <= parent.max[axis]);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn leaves_bound_their_triangles() {
+        let bvh = build(&scattered());
+        walk(&bvh, &mut |node, _, _| {
+            let first = node.left_or_first as usize;
+            let leaf = if node.count > 0 { &bvh.triangles[first..first + node.count as usize] } else { &[] };
+            for v in leaf.iter().flat_map(|t| [t.v0, t.v1, t.v2]) {
+                assert!((0..3).all(|axis| node.min[axis] <= v[axis] && v[axis] <= node.max[axis]));
+            }
+        });
+    }
+
+    #[test]
+    fn identical_centroids_stay_within_the_depth_limit() {
+        let meshes = triangles(std::iter::repeat_n([1.0, 2.0, 3.0], 200));
+        let bvh = build(&meshes);
+        let mut max_depth = 0;
+        let mut leaf_triangles = 0;
+        walk(&bvh, &mut |node, _, depth| {
+            max_depth = max_depth.max(depth);
+            leaf_triangles += node.count as usize;
+        });
+        assert!(max_depth <= MAX_DEPTH);
+        assert_eq!(leaf_triangles, meshes.len());
+    }
+
+    #[test]
+    fn lopsided_input_stays_within_the_depth_limit() {
+        // Each triangle twice as far out as the last, so every split peels off only a few
+        let meshes = triangles((0..120).map(|i| [2.0f32.powi(i - 60), 0.0, 0.0]));
+        let bvh = build(&meshes);
+        let mut max_depth = 0;
+        walk(&bvh, &mut |_, _, depth| max_depth = max_depth.max(depth));
+        assert!(max_depth <= MAX_DEPTH);
+    }
+}
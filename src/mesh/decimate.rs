@@ -0,0 +1,330 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use cgmath::{InnerSpace, Matrix3, SquareMatrix, Vector3};
+
+use crate::mesh::TriangleMesh;
+use crate::pipelines::mesh_pipeline::ModelVertex;
+
+// Boundary edges get a constraint plane this much stronger than a surface plane
+const BOUNDARY_WEIGHT: f64 = 1000.0;
+
+/// Quadric error metric edge collapse (Garland & Heckbert) down to about `target_triangles`.
+/// Collapses that would flip a triangle or pinch the surface into a non-manifold are skipped,
+/// so the result can end up above the target. Normals are recomputed.
+pub fn decimate(mesh: &TriangleMesh, target_triangles: usize) -> TriangleMesh {
+    if mesh.triangle_count() <= target_triangles {
+        return mesh.clone();
+    }
+
+    let mut positions: Vec<Vector3<f64>> = mesh
+        .vertices
+        .iter()
+        .map(|v| Vector3::from(v.position).cast().unwrap())
+        .collect();
+    let mut faces: Vec<[u32; 3]> = mesh.indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+    let mut face_alive = vec![true; faces.len()];
+    let mut vertex_alive = vec![true; positions.len()];
+    let mut vertex_faces: Vec<Vec<usize>> = vec![Vec::new(); positions.len()];
+    for (f, face) in faces.iter().enumerate() {
+        for v in face {
+            vertex_faces[*v as usize].push(f);
+        }
+    }
+
+    // Plane quadrics of the adjacent triangles, plus perpendicular planes along the boundary
+    let mut quadrics = vec![Quadric::default(); positions.len()];
+    let mut edge_faces: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+    for (f, face) in faces.iter().enumerate() {
+        let [a, b, c] = face.map(|v| positions[v as usize]);
+        let normal = (b - a).cross(c - a);
+        let area = normal.magnitude();
+        if area > 0.0 {
+            let normal = normal / area;
+            let plane = Quadric::plane(normal, -normal.dot(a), area * 0.5);
+            for v in face {
+                quadrics[*v as usize] += plane;
+            }
+        }
+        for i in 0..3 {
+            let (a, b) = (face[i], face[(i + 1) % 3]);
+            edge_faces.entry((a.min(b), a.max(b))).or_default().push(f);
+        }
+    }
+    for (&(a, b), adjacent) in &edge_faces {
+        if adjacent.len() != 1 {
+            continue;
+        }
+        let [p0, p1, p2] = faces[adjacent[0]].map(|v| positions[v as usize]);
+        let face_normal = (p1 - p0).cross(p2 - p0);
+        let edge = positions[b as usize] - positions[a as usize];
+        let normal = edge.cross(face_normal);
+        if normal.magnitude2() > 0.0 {
+            let normal = normal.normalize();
+            let plane = Quadric::plane(normal, -normal.dot(positions[a as usize]), edge.magnitude2() * BOUNDARY_WEIGHT);
+            quadrics[a as usize] += plane;
+            quadrics[b as usize] += plane;
+        }
+    }
+
+    let mut version = vec![0u32; positions.len()];
+    let mut heap = BinaryHeap::new();
+    for &(a, b) in edge_faces.keys() {
+        heap.push(Collapse::new(a, b, &quadrics, &positions, &version));
+    }
+    drop(edge_faces);
+
+    let mut triangle_count = faces.len();
+    while triangle_count > target_triangles {
+        let Some(collapse) = heap.pop() else {
+            break;
+        };
+        let (a, b) = (collapse.a as usize, collapse.b as usize);
+        if !vertex_alive[a] || !vertex_alive[b] || collapse.versions != [version[a], version[b]] {
+            continue;
+        }
+        vertex_faces[a].retain(|f| face_alive[*f]);
+        vertex_faces[b].retain(|f| face_alive[*f]);
+
+        // Link condition: the only shared neighbours are the opposite corners of the shared faces
+        let shared_faces = vertex_faces[a]
+            .iter()
+            .filter(|f| faces[**f].contains(&collapse.b))
+            .count();
+        let neighbours_a = neighbours(&faces, &vertex_faces[a], collapse.a);
+        let neighbours_b = neighbours(&faces, &vertex_faces[b], collapse.b);
+        let shared_neighbours = neighbours_a.iter().filter(|n| neighbours_b.contains(n)).count();
+        if shared_faces == 0 || shared_neighbours != shared_faces {
+            continue;
+        }
+
+        if flips(&faces, &positions, &vertex_faces[a], collapse.a, collapse.b, collapse.position)
+            || flips(&faces, &positions, &vertex_faces[b], collapse.b, collapse.a, collapse.position)
+        {
+            continue;
+        }
+
+        // Merge b into a
+        positions[a] = collapse.position;
+        quadrics[a] = quadrics[a] + quadrics[b];
+        vertex_alive[b] = false;
+        version[a] += 1;
+        for f in std::mem::take(&mut vertex_faces[b]) {
+            if faces[f].contains(&collapse.a) {
+                face_alive[f] = false;
+                triangle_count -= 1;
+            } else {
+                for v in faces[f].iter_mut().filter(|v| **v == collapse.b) {
+                    *v = collapse.a;
+                }
+                vertex_faces[a].push(f);
+            }
+        }
+        vertex_faces[a].retain(|f| face_alive[*f]);
+
+        for n in neighbours(&faces, &vertex_faces[a], collapse.a) {
+            heap.push(Collapse::new(collapse.a, n, &quadrics, &positions, &version));
+        }
+    }
+
+    // Compact the surviving vertices and triangles
+    let mut remap = vec![u32::MAX; positions.len()];
+    let mut result = TriangleMesh {
+        colors: mesh.colors.as_ref().map(|_| Vec::new()),
+        ..Default::default()
+    };
+    for (f, face) in faces.iter().enumerate() {
+        if !face_alive[f] {
+            continue;
+        }
+        for v in face {
+            let v = *v as usize;
+            if remap[v] == u32::MAX {
+                remap[v] = result.vertices.len() as u32;
+                result.vertices.push(ModelVertex {
+                    position: positions[v].cast().unwrap().into(),
+                    normal: mesh.vertices[v].normal,
+                });
+                if let (Some(colors), Some(source)) = (result.colors.as_mut(), mesh.colors.as_ref()) {
+                    colors.push(source[v]);
+                }
+            }
+            result.indices.push(remap[v]);
+        }
+    }
+    result.compute_normals();
+    result
+}
+
+fn neighbours(faces: &[[u32; 3]], adjacent: &[usize], vertex: u32) -> Vec<u32> {
+    let mut result: Vec<u32> = adjacent
+        .iter()
+        .flat_map(|f| faces[*f])
+        .filter(|v| *v != vertex)
+        .collect();
+    result.sort_unstable();
+    result.dedup();
+    result
+}
+
+// Would moving `vertex` to `position` turn over one of its triangles that doesn't contain `other`?
+fn flips(
+    faces: &[[u32; 3]],
+    positions: &[Vector3<f64>],
+    adjacent: &[usize],
+    vertex: u32,
+    other: u32,
+    position: Vector3<f64>,
+) -> bool {
+    adjacent.iter().any(|f| {
+        let face = faces[*f];
+        if face.contains(&other) {
+            return false;
+        }
+        let before = face.map(|v| positions[v as usize]);
+        let after = face.map(|v| if v == vertex { position } else { positions[v as usize] });
+        let normal_before = (before[1] - before[0]).cross(before[2] - before[0]);
+        let normal_after = (after[1] - after[0]).cross(after[2] - after[0]);
+        normal_before.dot(normal_after) <= 0.0
+    })
+}
+
+/// Symmetric 4x4 error quadric, upper triangle stored row by row.
+#[derive(Clone, Copy, Debug, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    // Squared distance to the plane n·p + d = 0, scaled by weight
+    fn plane(n: Vector3<f64>, d: f64, weight: f64) -> Self {
+        let (a, b, c) = (n.x, n.y, n.z);
+        Quadric([
+            a * a, a * b, a * c, a * d,
+            b * b, b * c, b * d,
+            c * c, c * d,
+            d * d,
+        ].map(|v| v * weight))
+    }
+
+    fn error(&self, p: Vector3<f64>) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p.x, p.y, p.z);
+        q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+            + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+            + q[7] * z * z + 2.0 * q[8] * z
+            + q[9]
+    }
+
+    // Position minimizing the error, None when the system is (near) singular
+    fn minimizer(&self) -> Option<Vector3<f64>> {
+        let q = &self.0;
+        let m = Matrix3::new(q[0], q[1], q[2], q[1], q[4], q[5], q[2], q[5], q[7]);
+        if m.determinant().abs() < 1e-12 {
+            return None;
+        }
+        Some(m.invert()? * -Vector3::new(q[3], q[6], q[8]))
+    }
+}
+
+impl std::ops::Add for Quadric {
+    type Output = Quadric;
+
+    fn add(self, other: Quadric) -> Quadric {
+        let mut sum = self.0;
+        for (s, o) in sum.iter_mut().zip(other.0) {
+            *s += o;
+        }
+        Quadric(sum)
+    }
+}
+
+impl std::ops::AddAssign for Quadric {
+    fn add_assign(&mut self, other: Quadric) {
+        *self = *self + other;
+    }
+}
+
+/// Candidate edge collapse, only valid while both vertices still have the recorded versions.
+struct Collapse {
+    cost: f64,
+    a: u32,
+    b: u32,
+    position: Vector3<f64>,
+    versions: [u32; 2],
+}
+
+impl Collapse {
+    fn new(a: u32, b: u32, quadrics: &[Quadric], positions: &[Vector3<f64>], version: &[u32]) -> Self {
+        let (ia, ib) = (a as usize, b as usize);
+        let quadric = quadrics[ia] + quadrics[ib];
+        let (pa, pb) = (positions[ia], positions[ib]);
+        let middle = (pa + pb) * 0.5;
+        // A nearly flat neighbourhood can put the minimizer far away, keep it near the edge
+        let optimal = quadric
+            .minimizer()
+            .filter(|p| (p - middle).magnitude2() <= (pb - pa).magnitude2());
+        let candidates = [optimal, Some(pa), Some(pb), Some(middle)];
+        let (cost, position) = candidates
+            .into_iter()
+            .flatten()
+            .map(|p| (quadric.error(p), p))
+            .min_by(|x, y| x.0.total_cmp(&y.0))
+            .unwrap();
+        Collapse {
+            cost,
+            a,
+            b,
+            position,
+            versions: [version[ia], version[ib]],
+        }
+    }
+}
+
+// Cheapest collapse first out of the max-heap
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost.total_cmp(&other.cost) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::sphere_mesh;
+
+    #[test]
+    fn decimated_sphere_stays_closed() {
+        let mesh = sphere_mesh(9.7);
+        let target = mesh.triangle_count() / 8;
+        let decimated = decimate(&mesh, target);
+        assert!(decimated.triangle_count() <= target + target / 10, "{} triangles", decimated.triangle_count());
+        assert!(decimated.is_closed());
+    }
+
+    #[test]
+    fn decimated_sphere_keeps_its_volume() {
+        let mesh = sphere_mesh(9.7);
+        let decimated = decimate(&mesh, mesh.triangle_count() / 8);
+        assert!((decimated.volume() - mesh.volume()).abs() < mesh.volume() * 0.03);
+    }
+
+    #[test]
+    fn small_meshes_are_left_alone() {
+        let mesh = sphere_mesh(4.2);
+        let decimated = decimate(&mesh, mesh.triangle_count());
+        assert_eq!(decimated.indices, mesh.indices);
+    }
+}
@@ -0,0 +1,351 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::mesh::TriangleMesh;
+
+/// File formats for exporting surfaces, e.g. for 3D printing or planning software.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshFormat {
+    StlBinary,
+    StlAscii,
+    Obj,
+    Ply,
+}
+
+impl MeshFormat {
+    /// Guesses the format from a file extension, `.stl` is written as binary.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "stl" => Some(MeshFormat::StlBinary),
+            "obj" => Some(MeshFormat::Obj),
+            "ply" => Some(MeshFormat::Ply),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MeshFormat::StlBinary | MeshFormat::StlAscii => "stl",
+            MeshFormat::Obj => "obj",
+            MeshFormat::Ply => "ply",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            MeshFormat::StlBinary => "model/stl",
+            MeshFormat::StlAscii => "model/stl",
+            MeshFormat::Obj => "model/obj",
+            MeshFormat::Ply => "application/octet-stream",
+        }
+    }
+}
+
+pub fn write(mesh: &TriangleMesh, format: MeshFormat, writer: &mut impl Write) -> io::Result<()> {
+    match format {
+        MeshFormat::StlBinary => write_stl_binary(mesh, writer),
+        MeshFormat::StlAscii => write_stl_ascii(mesh, writer),
+        MeshFormat::Obj => write_obj(mesh, writer),
+        MeshFormat::Ply => write_ply(mesh, writer),
+    }
+}
+
+pub fn to_bytes(mesh: &TriangleMesh, format: MeshFormat) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write(mesh, format, &mut bytes).expect("writing to memory can't fail");
+    bytes
+}
+
+/// Writes the mesh to a file, the format follows the extension.
+pub fn save(mesh: &TriangleMesh, path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    let format = MeshFormat::from_path(path).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("unknown mesh format: {}", path.display()))
+    })?;
+    let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+    write(mesh, format, &mut file)?;
+    file.flush()
+}
+
+/// Offers the mesh as a file download in the browser.
+#[cfg(web_platform)]
+pub fn download(mesh: &TriangleMesh, format: MeshFormat, file_name: &str) -> Result<(), wasm_bindgen::JsValue> {
+    use wasm_bindgen::closure::Closure;
+    use wasm_bindgen::JsCast;
+
+    let bytes = to_bytes(mesh, format);
+    let array = js_sys::Uint8Array::from(bytes.as_slice());
+    let parts = js_sys::Array::of1(&array);
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type(format.mime_type());
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)?;
+
+    let window = web_sys::window().ok_or("no window")?;
+    let document = window.document().ok_or("no document")?;
+    let anchor: web_sys::HtmlAnchorElement = document.create_element("a")?.dyn_into()?;
+    anchor.set_href(&url);
+    anchor.set_download(file_name);
+    anchor.click();
+
+    // The browser reads the blob after click() returns, revoking the URL right away can
+    // cancel the download
+    let revoke = Closure::once_into_js(move || {
+        let _ = web_sys::Url::revoke_object_url(&url);
+    });
+    window.set_timeout_with_callback_and_timeout_and_arguments_0(revoke.unchecked_ref(), 10_000)?;
+    Ok(())
+}
+
+fn triangles(mesh: &TriangleMesh) -> impl Iterator<Item = [usize; 3]> + '_ {
+    mesh.indices
+        .chunks_exact(3)
+        .map(|t| [t[0] as usize, t[1] as usize, t[2] as usize])
+}
+
+// STL stores facet normals only, taken from the geometry
+fn facet_normal(mesh: &TriangleMesh, triangle: [usize; 3]) -> [f32; 3] {
+    let [a, b, c] = triangle.map(|i| mesh.vertices[i].position);
+    let e1 = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+    let e2 = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+    let n = [
+        e1[1] * e2[2] - e1[2] * e2[1],
+        e1[2] * e2[0] - e1[0] * e2[2],
+        e1[0] * e2[1] - e1[1] * e2[0],
+    ];
+    let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+    if length > 0.0 {
+        n.map(|v| v / length)
+    } else {
+        [0.0; 3]
+    }
+}
+
+// Per vertex colours, ignored unless there is exactly one per vertex
+fn vertex_colors(mesh: &TriangleMesh) -> Option<&[[f32; 3]]> {
+    mesh.colors
+        .as_deref()
+        .filter(|colors| colors.len() == mesh.vertices.len())
+}
+
+// The files store display colours, the mesh linear ones
+fn to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn to_u8(c: f32) -> u8 {
+    (to_srgb(c) * 255.0).round() as u8
+}
+
+fn write_stl_binary(mesh: &TriangleMesh, writer: &mut impl Write) -> io::Result<()> {
+    let mut header = [0u8; 80];
+    let title = b"binary STL";
+    header[..title.len()].copy_from_slice(title);
+    writer.write_all(&header)?;
+    writer.write_all(&(mesh.triangle_count() as u32).to_le_bytes())?;
+
+    for triangle in triangles(mesh) {
+        for v in facet_normal(mesh, triangle) {
+            writer.write_all(&v.to_le_bytes())?;
+        }
+        for i in triangle {
+            for v in mesh.vertices[i].position {
+                writer.write_all(&v.to_le_bytes())?;
+            }
+        }
+
+        // Facet colour in the attribute word (VisCAM/SolidView: blue in bits 0-4, green in 5-9,
+        // red in 10-14, bit 15 valid)
+        let attribute = match vertex_colors(mesh) {
+            Some(colors) => {
+                let channel = |c: usize| {
+                    let mean = triangle.iter().map(|i| colors[*i][c]).sum::<f32>() / 3.0;
+                    (to_srgb(mean) * 31.0).round() as u16
+                };
+                0x8000 | channel(2) | (channel(1) << 5) | (channel(0) << 10)
+            }
+            None => 0,
+        };
+        writer.write_all(&attribute.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_stl_ascii(mesh: &TriangleMesh, writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "solid surface")?;
+    for triangle in triangles(mesh) {
+        let n = facet_normal(mesh, triangle);
+        writeln!(writer, "  facet normal {:e} {:e} {:e}", n[0], n[1], n[2])?;
+        writeln!(writer, "    outer loop")?;
+        for i in triangle {
+            let p = mesh.vertices[i].position;
+            writeln!(writer, "      vertex {:e} {:e} {:e}", p[0], p[1], p[2])?;
+        }
+        writeln!(writer, "    endloop")?;
+        writeln!(writer, "  endfacet")?;
+    }
+    writeln!(writer, "endsolid surface")
+}
+
+// Vertex colours use the common `v x y z r g b` extension
+fn write_obj(mesh: &TriangleMesh, writer: &mut impl Write) -> io::Result<()> {
+    let colors = vertex_colors(mesh);
+    for (i, vertex) in mesh.vertices.iter().enumerate() {
+        let p = vertex.position;
+        match colors {
+            Some(colors) => {
+                let c = colors[i].map(to_srgb);
+                writeln!(writer, "v {} {} {} {} {} {}", p[0], p[1], p[2], c[0], c[1], c[2])?
+            }
+            None => writeln!(writer, "v {} {} {}", p[0], p[1], p[2])?,
+        }
+    }
+    for vertex in &mesh.vertices {
+        let n = vertex.normal;
+        writeln!(writer, "vn {} {} {}", n[0], n[1], n[2])?;
+    }
+    for [a, b, c] in triangles(mesh) {
+        let (a, b, c) = (a + 1, b + 1, c + 1);
+        writeln!(writer, "f {a}//{a} {b}//{b} {c}//{c}")?;
+    }
+    Ok(())
+}
+
+// Binary little endian, positions and normals as float, colours as uchar
+fn write_ply(mesh: &TriangleMesh, writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format binary_little_endian 1.0")?;
+    let colors = vertex_colors(mesh);
+    writeln!(writer, "element vertex {}", mesh.vertices.len())?;
+    for property in ["x", "y", "z", "nx", "ny", "nz"] {
+        writeln!(writer, "property float {property}")?;
+    }
+    if colors.is_some() {
+        for property in ["red", "green", "blue"] {
+            writeln!(writer, "property uchar {property}")?;
+        }
+    }
+    writeln!(writer, "element face {}", mesh.triangle_count())?;
+    writeln!(writer, "property list uchar uint vertex_indices")?;
+    writeln!(writer, "end_header")?;
+
+    for (i, vertex) in mesh.vertices.iter().enumerate() {
+        for v in vertex.position.iter().chain(&vertex.normal) {
+            writer.write_all(&v.to_le_bytes())?;
+        }
+        if let Some(colors) = colors {
+            writer.write_all(&colors[i].map(to_u8))?;
+        }
+    }
+    for triangle in triangles(mesh) {
+        writer.write_all(&[3])?;
+        for i in triangle {
+            writer.write_all(&(i as u32).to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipelines::mesh_pipeline::ModelVertex;
+
+    // Tetrahedron, four vertices and four triangles
+    fn tetrahedron() -> TriangleMesh {
+        let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        let mut mesh = TriangleMesh {
+            vertices: positions
+                .into_iter()
+                .map(|position| ModelVertex { position, normal: [0.0; 3] })
+                .collect(),
+            indices: vec![0, 2, 1, 0, 1, 3, 0, 3, 2, 1, 2, 3],
+            colors: None,
+        };
+        mesh.compute_normals();
+        mesh
+    }
+
+    fn header(bytes: &[u8]) -> &str {
+        let end = bytes.windows(11).position(|w| w == b"end_header\n").unwrap() + 11;
+        std::str::from_utf8(&bytes[..end]).unwrap()
+    }
+
+    #[test]
+    fn binary_stl_has_a_header_and_a_record_per_triangle() {
+        let bytes = to_bytes(&tetrahedron(), MeshFormat::StlBinary);
+        assert_eq!(bytes.len(), 80 + 4 + 4 * 50);
+        assert_eq!(u32::from_le_bytes(bytes[80..84].try_into().unwrap()), 4);
+    }
+
+    #[test]
+    fn binary_stl_stores_facet_colours_blue_first() {
+        let mut mesh = tetrahedron();
+        mesh.set_color([1.0, 0.0, 0.0]);
+        let bytes = to_bytes(&mesh, MeshFormat::StlBinary);
+        // The attribute word ends each 50 byte record
+        for record in bytes[84..].chunks_exact(50) {
+            let attribute = u16::from_le_bytes([record[48], record[49]]);
+            assert_eq!(attribute & 0x8000, 0x8000);
+            assert_eq!([(attribute >> 10) & 31, (attribute >> 5) & 31, attribute & 31], [31, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn ascii_stl_has_a_facet_per_triangle() {
+        let bytes = to_bytes(&tetrahedron(), MeshFormat::StlAscii);
+        let text = String::from_utf8(bytes).unwrap();
+        assert!(text.starts_with("solid"));
+        assert_eq!(text.matches("facet normal").count(), 4);
+        assert_eq!(text.matches("vertex ").count(), 12);
+    }
+
+    #[test]
+    fn ply_counts_vertices_and_faces() {
+        let mut mesh = tetrahedron();
+        mesh.set_color([1.0, 0.5, 0.0]);
+        let bytes = to_bytes(&mesh, MeshFormat::Ply);
+        let header = header(&bytes);
+        assert!(header.contains("element vertex 4\n"));
+        assert!(header.contains("element face 4\n"));
+        assert!(header.contains("property uchar red\n"));
+        // 6 floats and 3 colour bytes per vertex, a count and 3 indices per face
+        assert_eq!(bytes.len() - header.len(), 4 * (24 + 3) + 4 * (1 + 12));
+    }
+
+    #[test]
+    fn obj_counts_vertices_and_faces() {
+        let bytes = to_bytes(&tetrahedron(), MeshFormat::Obj);
+        let text = String::from_utf8(bytes).unwrap();
+        assert_eq!(text.lines().filter(|l| l.starts_with("v ")).count(), 4);
+        assert_eq!(text.lines().filter(|l| l.starts_with("vn ")).count(), 4);
+        assert_eq!(text.lines().filter(|l| l.starts_with("f ")).count(), 4);
+    }
+
+    #[test]
+    fn colours_are_written_in_srgb() {
+        let mut mesh = tetrahedron();
+        mesh.set_color([0.5, 0.0, 1.0]);
+        let bytes = to_bytes(&mesh, MeshFormat::Ply);
+        let first_color = header(&bytes).len() + 24;
+        assert_eq!(bytes[first_color..first_color + 3], [188, 0, 255]);
+    }
+
+    #[test]
+    fn colours_that_dont_match_the_vertices_are_left_out() {
+        let mut mesh = tetrahedron();
+        mesh.colors = Some(vec![[1.0, 0.0, 0.0]; 2]);
+        for format in [MeshFormat::StlBinary, MeshFormat::Obj, MeshFormat::Ply] {
+            let bytes = to_bytes(&mesh, format);
+            assert!(!bytes.is_empty());
+        }
+        let text = String::from_utf8(to_bytes(&mesh, MeshFormat::Obj)).unwrap();
+        assert!(text.lines().filter(|l| l.starts_with("v ")).all(|l| l.split(' ').count() == 4));
+    }
+}
@@ -0,0 +1,287 @@
+use std::path::Path;
+
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, SquareMatrix, Vector3, Vector4};
+use gltf::mesh::Mode;
+use gltf::Gltf;
+
+use crate::mesh::{Material, TriangleMesh};
+use crate::pipelines::mesh_pipeline::ModelVertex;
+
+/// One drawable primitive of a glTF scene, baked into world space.
+pub struct SceneMesh {
+    pub name: Option<String>,
+    pub mesh: TriangleMesh,
+    pub material: Material,
+}
+
+/// All triangle primitives reachable from the default scene, with the node transforms applied.
+#[derive(Default)]
+pub struct GltfScene {
+    pub meshes: Vec<SceneMesh>,
+    pub images: Vec<Vec<u8>>, // encoded image files (png/jpeg) in glTF image order, not decoded
+}
+
+impl GltfScene {
+    /// Loads a `.gltf` or `.glb` file, external buffers and images are read relative to it.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, gltf::Error> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(gltf::Error::Io)?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        GltfScene::from_slice_with(&bytes, |uri| {
+            let relative = urlencoding::decode(uri).map_err(|e| {
+                gltf::Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+            })?;
+            std::fs::read(base.join(&*relative)).map_err(gltf::Error::Io)
+        })
+    }
+
+    /// Loads a self-contained glTF (GLB or embedded data URIs) from memory.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, gltf::Error> {
+        GltfScene::from_slice_with(bytes, |_| Err(gltf::Error::ExternalReferenceInSliceImport))
+    }
+
+    /// Loads from memory, `resolve` supplies the contents of external (non data) URIs.
+    pub fn from_slice_with(
+        bytes: &[u8],
+        mut resolve: impl FnMut(&str) -> Result<Vec<u8>, gltf::Error>,
+    ) -> Result<Self, gltf::Error> {
+        let gltf = Gltf::from_slice(bytes)?;
+
+        let mut buffers = Vec::new();
+        for buffer in gltf.buffers() {
+            let mut data = match buffer.source() {
+                gltf::buffer::Source::Bin => gltf.blob.clone().ok_or(gltf::Error::MissingBlob)?,
+                gltf::buffer::Source::Uri(uri) => read_uri(uri, &mut resolve)?,
+            };
+            if data.len() < buffer.length() {
+                return Err(gltf::Error::BufferLength {
+                    buffer: buffer.index(),
+                    expected: buffer.length(),
+                    actual: data.len(),
+                });
+            }
+            // GLB chunks are padded to 4 bytes
+            data.truncate(buffer.length());
+            buffers.push(data);
+        }
+
+        let mut images = Vec::new();
+        for image in gltf.images() {
+            images.push(match image.source() {
+                gltf::image::Source::View { view, .. } => {
+                    let data = &buffers[view.buffer().index()];
+                    let end = view.offset() + view.length();
+                    data.get(view.offset()..end)
+                        .ok_or(gltf::Error::BufferLength {
+                            buffer: view.buffer().index(),
+                            expected: end,
+                            actual: data.len(),
+                        })?
+                        .to_vec()
+                }
+                gltf::image::Source::Uri { uri, .. } => read_uri(uri, &mut resolve)?,
+            });
+        }
+
+        let mut scene = GltfScene::from_document(&gltf.document, &buffers);
+        scene.images = images;
+        Ok(scene)
+    }
+
+    /// Fetches a glTF over HTTP in the browser, external URIs are resolved against `url`.
+    #[cfg(web_platform)]
+    pub async fn fetch(url: &str) -> Result<Self, wasm_bindgen::JsValue> {
+        use std::collections::HashMap;
+
+        let base = web_sys::Url::new_with_base(url, &web_sys::window().ok_or("no window")?.location().href()?)?;
+        let bytes = fetch_bytes(&base.href()).await?;
+        let gltf = Gltf::from_slice(&bytes).map_err(|e| e.to_string())?;
+
+        // The resolver is synchronous, so everything external is downloaded up front
+        let uris: Vec<String> = gltf
+            .buffers()
+            .filter_map(|buffer| match buffer.source() {
+                gltf::buffer::Source::Uri(uri) => Some(uri.to_owned()),
+                gltf::buffer::Source::Bin => None,
+            })
+            .chain(gltf.images().filter_map(|image| match image.source() {
+                gltf::image::Source::Uri { uri, .. } => Some(uri.to_owned()),
+                gltf::image::Source::View { .. } => None,
+            }))
+            .filter(|uri| !uri.starts_with("data:"))
+            .collect();
+        let mut external = HashMap::new();
+        for uri in uris {
+            let resolved = web_sys::Url::new_with_base(&uri, &base.href())?;
+            external.insert(uri, fetch_bytes(&resolved.href()).await?);
+        }
+
+        GltfScene::from_slice_with(&bytes, |uri| {
+            external.remove(uri).ok_or(gltf::Error::ExternalReferenceInSliceImport)
+        })
+        .map_err(|e| e.to_string().into())
+    }
+
+    pub fn from_document(document: &gltf::Document, buffers: &[Vec<u8>]) -> Self {
+        let mut scene = GltfScene::default();
+        // Files without scenes still list their meshes, show those untransformed
+        match document.default_scene().or_else(|| document.scenes().next()) {
+            Some(root) => {
+                for node in root.nodes() {
+                    scene.add_node(&node, Matrix4::identity(), buffers);
+                }
+            }
+            None => {
+                for mesh in document.meshes() {
+                    scene.add_mesh(&mesh, Matrix4::identity(), buffers);
+                }
+            }
+        }
+        scene
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.meshes.iter().map(|m| m.mesh.triangle_count()).sum()
+    }
+
+    fn add_node(&mut self, node: &gltf::Node, parent: Matrix4<f32>, buffers: &[Vec<u8>]) {
+        let transform = parent * Matrix4::from(node.transform().matrix());
+        if let Some(mesh) = node.mesh() {
+            self.add_mesh(&mesh, transform, buffers);
+        }
+        for child in node.children() {
+            self.add_node(&child, transform, buffers);
+        }
+    }
+
+    fn add_mesh(&mut self, mesh: &gltf::Mesh, transform: Matrix4<f32>, buffers: &[Vec<u8>]) {
+        for primitive in mesh.primitives() {
+            if let Some(triangles) = read_primitive(&primitive, transform, buffers) {
+                self.meshes.push(SceneMesh {
+                    name: mesh.name().map(str::to_owned),
+                    mesh: triangles,
+                    material: read_material(&primitive.material()),
+                });
+            }
+        }
+    }
+}
+
+// Data URIs are decoded in place, anything else goes to the resolver
+fn read_uri(
+    uri: &str,
+    resolve: &mut impl FnMut(&str) -> Result<Vec<u8>, gltf::Error>,
+) -> Result<Vec<u8>, gltf::Error> {
+    match uri.strip_prefix("data:") {
+        Some(data) => {
+            let (_, payload) = data.split_once(";base64,").unwrap_or(("", data));
+            base64::decode(payload).map_err(gltf::Error::Base64)
+        }
+        None => resolve(uri),
+    }
+}
+
+#[cfg(web_platform)]
+async fn fetch_bytes(url: &str) -> Result<Vec<u8>, wasm_bindgen::JsValue> {
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_futures::JsFuture;
+
+    let window = web_sys::window().ok_or("no window")?;
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_str(url)).await?.dyn_into()?;
+    if !response.ok() {
+        return Err(format!("fetching {url} failed: {}", response.status()).into());
+    }
+    let buffer = JsFuture::from(response.array_buffer()?).await?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
+
+fn read_primitive(
+    primitive: &gltf::Primitive,
+    transform: Matrix4<f32>,
+    buffers: &[Vec<u8>],
+) -> Option<TriangleMesh> {
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+    let positions: Vec<[f32; 3]> = reader.read_positions()?.collect();
+    let vertex_count = positions.len() as u32;
+    let indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..vertex_count).collect(),
+    };
+    let mut indices = triangle_list(primitive.mode(), &indices)?;
+    if indices.iter().any(|i| *i >= vertex_count) {
+        return None;
+    }
+
+    let linear = Matrix3::from_cols(transform.x.truncate(), transform.y.truncate(), transform.z.truncate());
+    let normal_matrix = linear.invert().unwrap_or(Matrix3::identity()).transpose();
+    let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|n| n.collect());
+
+    let vertices = positions
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let position = (transform * Vector4::new(p[0], p[1], p[2], 1.0)).truncate();
+            let normal = normals
+                .as_ref()
+                .map(|n| normal_matrix * Vector3::from(n[i]))
+                .filter(|n| n.magnitude2() > 0.0)
+                .map(|n| n.normalize())
+                .unwrap_or(Vector3::new(0.0, 0.0, 0.0));
+            ModelVertex { position: position.into(), normal: normal.into() }
+        })
+        .collect();
+
+    // A mirroring node transform turns the winding inside out
+    if linear.determinant() < 0.0 {
+        for triangle in indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+
+    let mut mesh = TriangleMesh {
+        vertices,
+        indices,
+        colors: reader.read_colors(0).map(|c| c.into_rgb_f32().collect()),
+    };
+    if normals.is_none() {
+        mesh.compute_normals();
+    }
+    Some(mesh)
+}
+
+// Expands strips and fans, other modes (points, lines) aren't drawn
+fn triangle_list(mode: Mode, indices: &[u32]) -> Option<Vec<u32>> {
+    match mode {
+        Mode::Triangles => Some(indices[..indices.len() / 3 * 3].to_vec()),
+        Mode::TriangleStrip => Some(
+            (0..indices.len().saturating_sub(2))
+                .flat_map(|i| {
+                    if i % 2 == 0 {
+                        [indices[i], indices[i + 1], indices[i + 2]]
+                    } else {
+                        [indices[i + 1], indices[i], indices[i + 2]]
+                    }
+                })
+                .collect(),
+        ),
+        Mode::TriangleFan => Some(
+            (1..indices.len().saturating_sub(1))
+                .flat_map(|i| [indices[0], indices[i], indices[i + 1]])
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn read_material(material: &gltf::Material) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let strength = material.emissive_strength().unwrap_or(1.0);
+    Material {
+        base_color: pbr.base_color_factor(),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        emissive: material.emissive_factor().map(|e| e * strength),
+        transmission: material.transmission().map_or(0.0, |t| t.transmission_factor()),
+        ior: material.ior().unwrap_or(1.5),
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::pipelines::mesh_pipeline::ModelVertex;
+
+pub mod bvh;
+pub mod decimate;
+pub mod export;
+pub mod gltf_scene;
+pub mod marching_cubes;
+pub mod smooth;
+
+/// An indexed triangle mesh in patient space (mm), counter-clockwise winding seen from the
+/// side the normals point to.
+#[derive(Clone, Debug, Default)]
+pub struct TriangleMesh {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    pub colors: Option<Vec<[f32; 3]>>, // per vertex, linear rgb
+}
+
+/// Metallic-roughness surface description, as in glTF.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Material {
+    pub base_color: [f32; 4], // linear rgb + alpha
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+    pub transmission: f32, // 0 opaque, 1 clear glass
+    pub ior: f32,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color: [0.8, 0.8, 0.75, 1.0],
+            metallic: 0.0,
+            roughness: 0.6,
+            emissive: [0.0; 3],
+            transmission: 0.0,
+            ior: 1.5,
+        }
+    }
+}
+
+impl Material {
+    pub fn from_color(color: [f32; 3]) -> Self {
+        Self {
+            base_color: [color[0], color[1], color[2], 1.0],
+            ..Default::default()
+        }
+    }
+}
+
+impl TriangleMesh {
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// Gives every vertex the same colour, e.g. the label colour of a segmentation surface.
+    pub fn set_color(&mut self, color: [f32; 3]) {
+        self.colors = Some(vec![color; self.vertices.len()]);
+    }
+
+    /// Recomputes vertex normals from the area weighted normals of the adjacent triangles.
+    pub fn compute_normals(&mut self) {
+        let mut normals = vec![Vector3::new(0.0f32, 0.0, 0.0); self.vertices.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| Vector3::from(self.vertices[triangle[i] as usize].position));
+            let normal = (b - a).cross(c - a);
+            for i in triangle {
+                normals[*i as usize] += normal;
+            }
+        }
+        for (vertex, normal) in self.vertices.iter_mut().zip(normals) {
+            if normal.magnitude2() > 0.0 {
+                vertex.normal = normal.normalize().into();
+            }
+        }
+    }
+
+    /// Whether every edge is shared by exactly two triangles, i.e. the mesh encloses a volume.
+    pub fn is_closed(&self) -> bool {
+        let mut edges: HashMap<(u32, u32), u32> = HashMap::new();
+        for triangle in self.indices.chunks_exact(3) {
+            for i in 0..3 {
+                let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+        }
+        edges.values().all(|count| *count == 2)
+    }
+
+    /// Enclosed volume (mm³) for a closed, consistently wound mesh.
+    pub fn volume(&self) -> f32 {
+        self.indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                let [a, b, c] = [0, 1, 2].map(|i| Vector3::from(self.vertices[triangle[i] as usize].position));
+                a.dot(b.cross(c)) / 6.0
+            })
+            .sum()
+    }
+}
+
+/// Signed distance to a sphere around the grid centre, positive inside, as test input for
+/// surface extraction and processing.
+#[cfg(test)]
+pub fn sphere_volume(dims: [u32; 3], spacing: [f32; 3], radius: f32) -> crate::volume::Volume {
+    use cgmath::{Matrix4, Vector4};
+
+    let voxel_to_patient = Matrix4::from_nonuniform_scale(spacing[0], spacing[1], spacing[2]);
+    let center = crate::volume::Volume::grid_center(dims, voxel_to_patient);
+    let mut data = Vec::new();
+    for z in 0..dims[2] {
+        for y in 0..dims[1] {
+            for x in 0..dims[0] {
+                let p = (voxel_to_patient * Vector4::new(x as f32, y as f32, z as f32, 1.0)).truncate();
+                data.push(radius - (p - center).magnitude());
+            }
+        }
+    }
+    crate::volume::Volume {
+        dims,
+        voxel_to_patient,
+        data,
+    }
+}
+
+/// Closed surface of a sphere of `radius` voxels, extracted on a 25³ grid.
+#[cfg(test)]
+pub fn sphere_mesh(radius: f32) -> TriangleMesh {
+    marching_cubes::isosurface(&sphere_volume([25; 3], [1.0; 3], radius), 0.0)
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use cgmath::Vector3;
+
+use crate::mesh::TriangleMesh;
+
+/// Umbrella-operator smoothing. A negative `mu` gives Taubin's λ|μ filter, which removes the
+/// marching cubes terracing without the shrinkage of plain Laplacian smoothing.
+#[derive(Clone, Copy, Debug)]
+pub struct Smoothing {
+    pub iterations: u32,
+    pub lambda: f32,
+    pub mu: f32,
+    pub preserve_volume: bool, // rescales closed meshes about the centroid to the original volume
+}
+
+impl Smoothing {
+    pub fn laplacian(iterations: u32) -> Self {
+        Self { iterations, lambda: 0.5, mu: 0.0, preserve_volume: true }
+    }
+
+    // Barely shrinks by itself, rescaling would only move the surface off the data
+    pub fn taubin(iterations: u32) -> Self {
+        Self { iterations, lambda: 0.5, mu: -0.53, preserve_volume: false }
+    }
+}
+
+/// Smooths vertex positions in place and recomputes the normals. Boundary vertices stay fixed
+/// so open surfaces (cut at the volume edge) keep their outline.
+pub fn smooth(mesh: &mut TriangleMesh, settings: &Smoothing) {
+    if mesh.is_empty() {
+        return;
+    }
+
+    let (neighbours, boundary) = topology(mesh);
+    // An open mesh encloses no volume to preserve
+    let preserve_volume = settings.preserve_volume && mesh.is_closed();
+    let original_volume = mesh.volume();
+
+    let mut positions: Vec<Vector3<f32>> =
+        mesh.vertices.iter().map(|v| Vector3::from(v.position)).collect();
+    for _ in 0..settings.iterations {
+        relax(&mut positions, &neighbours, &boundary, settings.lambda);
+        if settings.mu != 0.0 {
+            relax(&mut positions, &neighbours, &boundary, settings.mu);
+        }
+    }
+    for (vertex, position) in mesh.vertices.iter_mut().zip(&positions) {
+        vertex.position = (*position).into();
+    }
+
+    if preserve_volume {
+        let volume = mesh.volume();
+        // Unreferenced vertices (e.g. left over from decimation) don't pull the centroid
+        let referenced: Vec<usize> = (0..positions.len()).filter(|i| !neighbours[*i].is_empty()).collect();
+        if volume.abs() > f32::EPSILON && volume.signum() == original_volume.signum() && !referenced.is_empty() {
+            let scale = (original_volume / volume).cbrt();
+            let centroid = referenced
+                .iter()
+                .fold(Vector3::new(0.0, 0.0, 0.0), |sum, i| sum + positions[*i])
+                / referenced.len() as f32;
+            for i in referenced.into_iter().filter(|i| !boundary[*i]) {
+                mesh.vertices[i].position = (centroid + (positions[i] - centroid) * scale).into();
+            }
+        }
+    }
+
+    mesh.compute_normals();
+}
+
+fn relax(positions: &mut [Vector3<f32>], neighbours: &[Vec<u32>], boundary: &[bool], factor: f32) {
+    let previous = positions.to_vec();
+    for (i, position) in positions.iter_mut().enumerate() {
+        if boundary[i] || neighbours[i].is_empty() {
+            continue;
+        }
+        let sum = neighbours[i]
+            .iter()
+            .fold(Vector3::new(0.0, 0.0, 0.0), |sum, n| sum + previous[*n as usize]);
+        let mean = sum / neighbours[i].len() as f32;
+        *position += (mean - previous[i]) * factor;
+    }
+}
+
+// Vertex neighbours over the edges, and which vertices lie on an edge used by a single triangle
+fn topology(mesh: &TriangleMesh) -> (Vec<Vec<u32>>, Vec<bool>) {
+    let mut edge_count: HashMap<(u32, u32), u32> = HashMap::new();
+    for triangle in mesh.indices.chunks_exact(3) {
+        for i in 0..3 {
+            let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+            *edge_count.entry((a.min(b), a.max(b))).or_default() += 1;
+        }
+    }
+
+    let mut neighbours = vec![Vec::new(); mesh.vertices.len()];
+    let mut boundary = vec![false; mesh.vertices.len()];
+    for (&(a, b), &count) in &edge_count {
+        neighbours[a as usize].push(b);
+        neighbours[b as usize].push(a);
+        if count == 1 {
+            boundary[a as usize] = true;
+            boundary[b as usize] = true;
+        }
+    }
+    (neighbours, boundary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::sphere_mesh;
+    use crate::pipelines::mesh_pipeline::ModelVertex;
+
+    // Bumpy square patch, open along its outline
+    fn patch(n: u32) -> TriangleMesh {
+        let mut mesh = TriangleMesh::default();
+        for y in 0..n {
+            for x in 0..n {
+                let bump = ((x * 7 + y * 13) % 5) as f32 * 0.3;
+                mesh.vertices.push(ModelVertex {
+                    position: [x as f32, y as f32, bump],
+                    normal: [0.0, 0.0, 1.0],
+                });
+            }
+        }
+        for y in 0..n - 1 {
+            for x in 0..n - 1 {
+                let i = y * n + x;
+                mesh.indices.extend_from_slice(&[i, i + 1, i + n + 1, i, i + n + 1, i + n]);
+            }
+        }
+        mesh
+    }
+
+    #[test]
+    fn boundary_stays_in_place() {
+        let n = 8;
+        let original = patch(n);
+        for settings in [Smoothing::laplacian(10), Smoothing::taubin(10)] {
+            let mut mesh = original.clone();
+            smooth(&mut mesh, &settings);
+            for (i, (before, after)) in original.vertices.iter().zip(&mesh.vertices).enumerate() {
+                let (x, y) = (i as u32 % n, i as u32 / n);
+                if x == 0 || y == 0 || x == n - 1 || y == n - 1 {
+                    assert_eq!(before.position, after.position);
+                }
+            }
+            assert!(original.vertices.iter().zip(&mesh.vertices).any(|(a, b)| a.position != b.position));
+        }
+    }
+
+    #[test]
+    fn laplacian_keeps_the_volume_of_a_closed_mesh() {
+        let mut mesh = sphere_mesh(9.7);
+        let volume = mesh.volume();
+        smooth(&mut mesh, &Smoothing::laplacian(10));
+        assert!((mesh.volume() - volume).abs() < volume * 1e-3);
+    }
+}
@@ -0,0 +1,276 @@
+use serde::{Deserialize, Serialize};
+
+/// Size of one G-buffer texel written by the tracers: albedo and depth, normal and padding.
+pub const GBUFFER_TEXEL_SIZE: wgpu::BufferAddress = 32;
+
+const MAX_ITERATIONS: u32 = 5;
+const UNIFORM_STRIDE: wgpu::BufferAddress = 256; // min_uniform_buffer_offset_alignment
+
+/// Strength of the à-trous filter. Each iteration doubles the tap distance, so five of them
+/// cover a 61 pixel footprint.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DenoiseSettings {
+    pub enabled: bool,
+    pub iterations: u32,   // up to 5
+    pub sigma_color: f32,  // relative luminance difference, divided by sqrt(frames)
+    pub sigma_normal: f32, // exponent on the normal cosine
+    pub sigma_depth: f32,  // relative depth difference per pixel of tap distance
+    pub sigma_albedo: f32,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            iterations: 4,
+            sigma_color: 4.0,
+            sigma_normal: 64.0,
+            sigma_depth: 0.01,
+            sigma_albedo: 0.1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DenoiseUniform {
+    step: u32,
+    frames: f32,
+    sigma_color: f32,
+    sigma_normal: f32,
+    sigma_depth: f32,
+    sigma_albedo: f32,
+    _pad: [f32; 2],
+}
+
+/// Filters the output of a path tracer with the G-buffer it writes, between the tracer and
+/// the tone mapping pass. Ping-pongs between two textures and always ends in `texture`.
+pub struct DenoisePipeline {
+    pipeline: wgpu::ComputePipeline,
+    pub texture: wgpu::Texture,
+    uniform_buffer: wgpu::Buffer,
+    // Input -> output pairs: source -> texture, source -> temp, temp -> texture, texture -> temp
+    bind_groups: [wgpu::BindGroup; 4],
+    settings: DenoiseSettings,
+    active: bool, // the source is noisy this frame
+}
+
+impl DenoisePipeline {
+    pub fn create_view(&self) -> wgpu::TextureView {
+        self.texture.create_view(&Default::default())
+    }
+
+    /// `source` is the tracer's output, `gbuffer` holds `GBUFFER_TEXEL_SIZE` bytes per pixel.
+    pub fn new(
+        surface_config: &wgpu::SurfaceConfiguration,
+        device: &wgpu::Device,
+        source: &wgpu::TextureView,
+        gbuffer: &wgpu::Buffer,
+    ) -> Self {
+        let create_texture = |label| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: surface_config.width,
+                    height: surface_config.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba16Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+                view_formats: &[],
+            })
+        };
+        let texture = create_texture("Denoised Framebuffer");
+        let temp = create_texture("Denoise Scratch");
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("denoise buffer"),
+            size: UNIFORM_STRIDE * MAX_ITERATIONS as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader_denoise"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/denoise.wgsl").into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("denoise bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: texture.format(),
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true, // one slot per iteration
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<DenoiseUniform>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let texture_view = texture.create_view(&Default::default());
+        let temp_view = temp.create_view(&Default::default());
+        let create_bind_group = |input: &wgpu::TextureView, output: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(input),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(output),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: gbuffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer: &uniform_buffer,
+                            offset: 0,
+                            size: wgpu::BufferSize::new(std::mem::size_of::<DenoiseUniform>() as u64),
+                        }),
+                    },
+                ],
+            })
+        };
+        let bind_groups = [
+            create_bind_group(source, &texture_view),
+            create_bind_group(source, &temp_view),
+            create_bind_group(&temp_view, &texture_view),
+            create_bind_group(&texture_view, &temp_view),
+        ];
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Denoise Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Denoise Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("main"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            texture,
+            uniform_buffer,
+            bind_groups,
+            settings: DenoiseSettings::default(),
+            active: false,
+        }
+    }
+
+    pub fn settings(&self) -> DenoiseSettings {
+        self.settings
+    }
+
+    /// Takes effect with the next `update`.
+    pub fn set_settings(&mut self, settings: DenoiseSettings) {
+        self.settings = settings;
+    }
+
+    /// `active` is false for deterministic renderings, which are passed through unfiltered.
+    /// `frames` is the number of frames the source has accumulated.
+    pub fn update(&mut self, queue: &wgpu::Queue, active: bool, frames: u32) {
+        self.active = active && self.settings.enabled && self.settings.iterations > 0;
+        let mut uniforms = vec![0u8; (UNIFORM_STRIDE * MAX_ITERATIONS as wgpu::BufferAddress) as usize];
+        for i in 0..self.iterations() {
+            let uniform = DenoiseUniform {
+                step: if self.active { 1 << i } else { 0 },
+                frames: frames as f32,
+                sigma_color: self.settings.sigma_color,
+                sigma_normal: self.settings.sigma_normal,
+                sigma_depth: self.settings.sigma_depth,
+                sigma_albedo: self.settings.sigma_albedo,
+                _pad: [0.0; 2],
+            };
+            let offset = (UNIFORM_STRIDE * i as wgpu::BufferAddress) as usize;
+            uniforms[offset..offset + std::mem::size_of::<DenoiseUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(&uniform));
+        }
+        queue.write_buffer(&self.uniform_buffer, 0, &uniforms);
+    }
+
+    // A single copying pass when inactive
+    fn iterations(&self) -> u32 {
+        if self.active {
+            self.settings.iterations.min(MAX_ITERATIONS)
+        } else {
+            1
+        }
+    }
+
+    pub fn pass(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Denoise Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+
+        let iterations = self.iterations();
+        for i in 0..iterations {
+            // Every iteration writes the other texture, the last one `texture`
+            let to_texture = (iterations - 1 - i).is_multiple_of(2);
+            let bind_group = match (i == 0, to_texture) {
+                (true, true) => &self.bind_groups[0],
+                (true, false) => &self.bind_groups[1],
+                (false, true) => &self.bind_groups[2],
+                (false, false) => &self.bind_groups[3],
+            };
+            let offset = (UNIFORM_STRIDE * i as wgpu::BufferAddress) as wgpu::DynamicOffset;
+            compute_pass.set_bind_group(0, bind_group, &[offset]);
+            compute_pass.dispatch_workgroups(
+                self.texture.width().div_ceil(8),
+                self.texture.height().div_ceil(8),
+                1,
+            );
+        }
+    }
+}
@@ -1,11 +1,228 @@
 use crate::camera::Camera;
-use nifti::{IntoNdArray, NiftiObject, ReaderOptions};
+use crate::environment::{Environment, EnvironmentMap, EnvironmentSettings};
+use crate::labelmap::LabelMap;
+use crate::pipelines::denoise_pipeline::GBUFFER_TEXEL_SIZE;
+use crate::transfer_function::TransferFunction;
+use crate::volume::{Volume, VolumeError, VolumeUniform};
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+use serde::{Deserialize, Serialize};
 use wgpu::util::DeviceExt;
 
+// Relative to the assets directory, the LCTSC test data isn't part of the repository
+pub const LCTSC_CT_PATH: &str = "LCTSC-Test-S1-101/IMAGES/LCTSC_TEST_S1_101_0_CT_0.nii.gz";
+// Optional second scan of the same patient (e.g. PET), fused with the CT
+pub const LCTSC_FUSION_PATH: &str = "LCTSC-Test-S1-101/IMAGES/LCTSC_TEST_S1_101_0_fusion.nii.gz";
+// Organ segmentation of the same scan, one label per structure
+pub const LCTSC_LABEL_PATH: &str = "LCTSC-Test-S1-101/IMAGES/LCTSC_TEST_S1_101_0_CT_0_labels.nii.gz";
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MedicalMode {
+    Mip = 0,
+    Dvr = 1,
+    Mpr = 2,
+    Cinematic = 3,
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SliceAxis {
+    Sagittal = 0,
+    Coronal = 1,
+    Axial = 2,
+}
+
+/// How the fusion volume is combined with the primary volume.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BlendMode {
+    Alpha = 0,
+    Additive = 1,
+    Checkerboard = 2,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MedicalSettings {
+    pub mode: MedicalMode,
+    pub window_center: f32,
+    pub window_width: f32,
+    pub slice_axis: SliceAxis,
+    pub slice_index: u32,
+    pub overlay_opacity: f32,
+    pub step_size: f32, // in voxels
+    pub blend_mode: BlendMode,
+    pub fusion_opacity: f32,
+    pub checker_size: f32, // in mm
+}
+
+impl Default for MedicalSettings {
+    fn default() -> Self {
+        Self {
+            mode: MedicalMode::Mip,
+            window_center: 40.0,
+            window_width: 400.0,
+            slice_axis: SliceAxis::Axial,
+            slice_index: 0,
+            overlay_opacity: 0.6,
+            step_size: 0.5,
+            blend_mode: BlendMode::Alpha,
+            fusion_opacity: 0.5,
+            checker_size: 20.0,
+        }
+    }
+}
+
+/// Directional light shared by DVR shading and the path tracer, and the strength of the
+/// precomputed shadows and ambient occlusion used by DVR.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightingSettings {
+    pub light_direction: [f32; 3], // towards the light, patient space
+    pub shadows: f32,                  // 0: off, 1: full strength
+    pub occlusion: f32,                // 0: off, 1: full strength
+    pub ambient: f32,                  // share of ambient vs. directional light
+    pub ao_radius: f32,                // in mm
+}
+
+impl Default for LightingSettings {
+    fn default() -> Self {
+        Self {
+            light_direction: [0.4, -0.6, 0.7],
+            shadows: 0.8,
+            occlusion: 0.6,
+            ambient: 0.35,
+            ao_radius: 8.0,
+        }
+    }
+}
+
+/// Lighting and scattering parameters of the volumetric path tracer. The sky and ground
+/// colours are the ambient light until an environment map is set.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CinematicSettings {
+    pub light_color: [f32; 3],
+    pub sky_color: [f32; 3],
+    pub ground_color: [f32; 3],
+    pub density_scale: f32, // extinction per mm at full opacity
+    pub anisotropy: f32,    // Henyey-Greenstein g, -1 (back) to 1 (forward)
+    pub max_bounces: u32,
+}
+
+impl Default for CinematicSettings {
+    fn default() -> Self {
+        Self {
+            light_color: [8.0, 7.6, 7.0],
+            sky_color: [0.45, 0.55, 0.7],
+            ground_color: [0.15, 0.12, 0.1],
+            density_scale: 1.0,
+            anisotropy: 0.2,
+            max_bounces: 4,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CinematicUniform {
+    light_direction: [f32; 3],
+    frame_index: u32,
+    light_color: [f32; 3],
+    max_bounces: u32,
+    sky_color: [f32; 3],
+    density_scale: f32,
+    ground_color: [f32; 3],
+    anisotropy: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightingUniform {
+    light_direction: [f32; 3],
+    shadows: f32,
+    grid_dims: [u32; 3],
+    occlusion: f32,
+    ambient: f32,
+    ao_radius: f32,
+    _pad: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Details {
+    screen_width: f32,
+    screen_height: f32,
+    mode: u32,
+    step_size: f32,
+    window_center: f32,
+    window_width: f32,
+    tf_min: f32,
+    tf_max: f32,
+    slice_axis: u32,
+    slice_index: f32,
+    overlay_opacity: f32,
+    num_labels: u32,
+    blend_mode: u32,
+    fusion_opacity: f32,
+    checker_size: f32,
+    has_fusion: u32,
+    fusion_tf_min: f32,
+    fusion_tf_max: f32,
+    fusion_value_min: f32, // the fusion texture holds 0..1 over its value range
+    fusion_value_range: f32,
+}
+
 pub struct MedicalPipeline {
     pub pipeline: wgpu::ComputePipeline,
+    pub cinematic_pipeline: wgpu::ComputePipeline,
+    pub light_pipeline: wgpu::ComputePipeline,
     pub bind_group: wgpu::BindGroup,
     pub texture: wgpu::Texture,
+    pub settings: MedicalSettings,
+    pub lighting: LightingSettings,
+    pub cinematic: CinematicSettings,
+    pub labels: LabelMap,
+    pub transfer_function: TransferFunction,
+    pub fusion_transfer_function: TransferFunction,
+    /// Registration of the fusion volume, applied on top of its own voxel-to-patient transform.
+    pub fusion_registration: Matrix4<f32>,
+    dims: [u32; 3],
+    voxel_to_patient: Matrix4<f32>,
+    min_spacing: f32,
+    light_grid: [u32; 3],
+    fusion_grid: Option<([u32; 3], Matrix4<f32>)>,
+    fusion_value_range: (f32, f32),
+    // Everything bound next to the size dependent targets, to bind again after a resize
+    bind_group_layout: wgpu::BindGroupLayout,
+    input_texture_view: wgpu::TextureView,
+    input_texture_sampler: wgpu::Sampler,
+    fusion_texture_sampler: wgpu::Sampler,
+    label_texture_view: wgpu::TextureView,
+    fusion_texture_view: wgpu::TextureView,
+    camera_buffer: wgpu::Buffer,
+    volume_buffer: wgpu::Buffer,
+    light_volume_buffer: wgpu::Buffer,
+    accumulation_buffer: wgpu::Buffer,
+    details_buffer: wgpu::Buffer,
+    labels_buffer: wgpu::Buffer,
+    transfer_function_buffer: wgpu::Buffer,
+    fusion_volume_buffer: wgpu::Buffer,
+    fusion_transfer_function_buffer: wgpu::Buffer,
+    cinematic_buffer: wgpu::Buffer,
+    lighting_buffer: wgpu::Buffer,
+    gbuffer: wgpu::Buffer,
+    environment: Environment, // bind group 1, lights the cinematic mode
+    // Copies of what the lookup table buffers hold, to bake them again only after an edit
+    uploaded_transfer_function: TransferFunction,
+    uploaded_fusion_transfer_function: TransferFunction,
+    // The light volume is recomputed on the first DVR frame after its inputs change
+    recompute_light: bool,
+    light_stale: bool,
+    last_light_state: Vec<u8>,
+    // Progressive accumulation restarts whenever anything that was uploaded changes
+    frame_index: u32,
+    last_state: Vec<u8>,
 }
 
 impl MedicalPipeline {
@@ -13,37 +230,34 @@ impl MedicalPipeline {
         self.texture.create_view(&Default::default())
     }
 
+    /// `label_volume` is an optional segmentation aligned with `volume`, voxel for voxel, it is
+    /// an error if the grids differ.
+    /// `fusion_volume` is an optional second (e.g. PET) volume on its own grid, resampled into
+    /// the primary volume's patient space in the shader.
+    /// `mesh_depth` and `mesh_color` are the multisampled depth buffer and resolved colour of the
+    /// mesh pass; rays stop at mesh surfaces so embedded geometry is occluded correctly.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         surface_config: &wgpu::SurfaceConfiguration,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         camera: &Camera,
-    ) -> Self {
-        let obj = ReaderOptions::new().read_file("/home/stephan/Downloads/LCTSC_NIFTI_TestData/LCTSC-Test-S1-101/IMAGES/LCTSC_TEST_S1_101_0_CT_0.nii.gz").unwrap();
-        // let header = &obj.header();
-        let volume = obj.into_volume().into_ndarray::<f32>().unwrap();
-
-        let shape = volume.shape();
-        let width = shape[0] as u32;
-        let height = shape[1] as u32;
-        let depth = shape[2] as u32;
-
-        let input_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Medical Volume Texture"),
-            size: wgpu::Extent3d {
-                width,
-                height,
-                depth_or_array_layers: depth,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D3,
-            format: wgpu::TextureFormat::R32Float,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING
-                | wgpu::TextureUsages::COPY_SRC
-                | wgpu::TextureUsages::COPY_DST,
-            view_formats: Default::default(),
-        });
+        volume: &Volume,
+        label_volume: Option<&Volume>,
+        fusion_volume: Option<&Volume>,
+        mesh_depth: &wgpu::TextureView,
+        mesh_color: &wgpu::TextureView,
+    ) -> Result<Self, VolumeError> {
+        let volume_uniform = volume.uniform()?;
+        let fusion_grid = match fusion_volume {
+            Some(fusion_volume) => {
+                fusion_volume.uniform()?;
+                Some((fusion_volume.dims, fusion_volume.voxel_to_patient))
+            }
+            None => None,
+        };
+
+        let input_texture = volume.create_texture(device, queue);
         let input_texture_view = input_texture.create_view(&Default::default());
 
         let input_texture_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
@@ -61,69 +275,141 @@ impl MedicalPipeline {
             border_color: None,
         });
 
-        let flat: Vec<f32> = volume.into_raw_vec();
-        let flat_bytes = bytemuck::cast_slice(&flat);
-        queue.write_texture(
-            wgpu::TexelCopyTextureInfo {
-                texture: &input_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            flat_bytes,
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(width * 4),
-                rows_per_image: Some(height),
-            },
-            wgpu::Extent3d {
-                width,
-                height,
-                depth_or_array_layers: depth,
-            },
-        );
+        // Without a segmentation bind a single background voxel and an empty label table
+        let (label_texture, labels) = match label_volume {
+            Some(label_volume) => {
+                volume.check_grid(label_volume)?;
+                (
+                    label_volume.create_label_texture(device, queue),
+                    LabelMap::new(label_volume.max_label()),
+                )
+            }
+            None => (
+                Volume::placeholder().create_label_texture(device, queue),
+                LabelMap::new(0),
+            ),
+        };
+        let label_texture_view = label_texture.create_view(&Default::default());
+
+        // Unlike the CT and the labels the fusion volume is interpolated, it is usually much
+        // coarser than the grid it is shown on
+        let placeholder = Volume::placeholder();
+        let fusion_source = fusion_volume.unwrap_or(&placeholder);
+        let fusion_texture = fusion_source.create_normalized_texture(device, queue);
+        let fusion_texture_view = fusion_texture.create_view(&Default::default());
+        let fusion_value_range = fusion_source.range();
+        let fusion_texture_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Fusion Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 0.0,
+            compare: None,
+            anisotropy_clamp: 1,
+            border_color: None,
+        });
+
+        let fusion_transfer_function = match fusion_volume {
+            Some(fusion_volume) => {
+                let (min, max) = fusion_volume.range();
+                TransferFunction::hot_metal(min, max)
+            }
+            None => TransferFunction::ramp(0.0, 1.0),
+        };
+        let fusion_transfer_function_buffer =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("fusion transfer function buffer"),
+                contents: bytemuck::cast_slice(&fusion_transfer_function.bake()),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            });
+
+        let fusion_volume_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("fusion volume buffer"),
+            contents: bytemuck::cast_slice(&[Volume::placeholder().uniform()?]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let labels_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("labels buffer"),
+            contents: bytemuck::cast_slice(labels.styles()),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let transfer_function = TransferFunction::ct_default();
+        let transfer_function_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("transfer function buffer"),
+            contents: bytemuck::cast_slice(&transfer_function.bake()),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let volume_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("volume buffer"),
+            contents: bytemuck::cast_slice(&[volume_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let settings = MedicalSettings {
+            slice_index: volume.dims[2] / 2,
+            ..Default::default()
+        };
+
+        // Light volume at half resolution, capped to keep recomputation interactive
+        let light_grid = volume.dims.map(|d| d.div_ceil(2).clamp(1, 128));
+        let light_volume_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("light volume buffer"),
+            size: (light_grid[0] * light_grid[1] * light_grid[2] * 8) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let lighting_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("lighting buffer"),
+            size: std::mem::size_of::<LightingUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let cinematic = CinematicSettings::default();
 
-        let details = vec![
-            surface_config.width as f32,
-            surface_config.height as f32,
-            width as f32,
-            height as f32,
-            depth as f32,
-        ];
-        let details_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        let cinematic_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("cinematic buffer"),
+            size: std::mem::size_of::<CinematicUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let details_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("details buffer"),
-            contents: bytemuck::cast_slice(&details),
+            size: std::mem::size_of::<Details>() as wgpu::BufferAddress,
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
 
         ////
 
+        let environment = Environment::new(device, queue);
+
         let shader_common_src = include_str!("shaders/common.wgsl");
         let shader_medical_src = include_str!("shaders/medical.wgsl");
-        let shader_combined = format!("{}\n{}", shader_common_src, shader_medical_src);
+        let shader_cinematic_src = include_str!("shaders/cinematic.wgsl");
+        let shader_lighting_src = include_str!("shaders/lighting.wgsl");
+        let shader_environment_src = include_str!("shaders/environment.wgsl");
+        let shader_combined = format!(
+            "{}\n{}\n{}\n{}\n{}",
+            shader_common_src, shader_medical_src, shader_cinematic_src, shader_lighting_src, shader_environment_src
+        );
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Shader_ray"),
             source: wgpu::ShaderSource::Wgsl(shader_combined.into()),
         });
 
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: None,
-            size: wgpu::Extent3d {
-                width: surface_config.width,
-                height: surface_config.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::COPY_DST
-                | wgpu::TextureUsages::TEXTURE_BINDING
-                | wgpu::TextureUsages::STORAGE_BINDING,
-            view_formats: Default::default(),
-        });
-
+        let (texture, accumulation_buffer, gbuffer) =
+            MedicalPipeline::create_targets(device, surface_config.width, surface_config.height);
         let texture_view = texture.create_view(&Default::default());
 
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -175,39 +461,185 @@ impl MedicalPipeline {
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
                     count: None,
                 },
-            ],
-        });
-
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        multisampled: false,
+                    },
+                    count: None,
                 },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&input_texture_view),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: camera.buffer.as_entire_binding(),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: details_buffer.as_entire_binding(),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: wgpu::BindingResource::Sampler(&input_texture_sampler),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 11,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 12,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 13,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 14,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 15,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 16,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: true,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 17,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 18,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 19,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
                 },
             ],
         });
 
+        let bind_group = MedicalPipeline::create_bind_group(
+            device,
+            &bind_group_layout,
+            [
+                wgpu::BindingResource::TextureView(&texture_view),
+                wgpu::BindingResource::TextureView(&input_texture_view),
+                camera.buffer.as_entire_binding(),
+                details_buffer.as_entire_binding(),
+                wgpu::BindingResource::Sampler(&input_texture_sampler),
+                wgpu::BindingResource::TextureView(&label_texture_view),
+                labels_buffer.as_entire_binding(),
+                transfer_function_buffer.as_entire_binding(),
+                volume_buffer.as_entire_binding(),
+                wgpu::BindingResource::TextureView(&fusion_texture_view),
+                fusion_volume_buffer.as_entire_binding(),
+                fusion_transfer_function_buffer.as_entire_binding(),
+                accumulation_buffer.as_entire_binding(),
+                cinematic_buffer.as_entire_binding(),
+                light_volume_buffer.as_entire_binding(),
+                lighting_buffer.as_entire_binding(),
+                wgpu::BindingResource::TextureView(mesh_depth),
+                wgpu::BindingResource::TextureView(mesh_color),
+                gbuffer.as_entire_binding(),
+                wgpu::BindingResource::Sampler(&fusion_texture_sampler),
+            ],
+        );
+
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Compute Pipeline Layout"),
-            bind_group_layouts: &[&bind_group_layout],
+            bind_group_layouts: &[&bind_group_layout, &environment.bind_group_layout],
             push_constant_ranges: &[],
         });
 
@@ -220,11 +652,343 @@ impl MedicalPipeline {
             cache: None,
         });
 
-        Self {
+        let cinematic_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Cinematic Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("cinematic_main"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        let light_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Light Volume Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("light_main"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        let spacing = volume.spacing();
+
+        let mut medical_pipeline = Self {
             pipeline,
+            cinematic_pipeline,
+            light_pipeline,
             bind_group,
             texture,
+            settings,
+            lighting: LightingSettings::default(),
+            cinematic,
+            labels,
+            uploaded_transfer_function: transfer_function.clone(),
+            uploaded_fusion_transfer_function: fusion_transfer_function.clone(),
+            transfer_function,
+            fusion_transfer_function,
+            fusion_registration: Matrix4::identity(),
+            dims: volume.dims,
+            voxel_to_patient: volume.voxel_to_patient,
+            min_spacing: spacing.x.min(spacing.y).min(spacing.z),
+            light_grid,
+            fusion_grid,
+            fusion_value_range,
+            bind_group_layout,
+            input_texture_view,
+            input_texture_sampler,
+            fusion_texture_sampler,
+            label_texture_view,
+            fusion_texture_view,
+            camera_buffer: camera.buffer.clone(),
+            volume_buffer,
+            light_volume_buffer,
+            accumulation_buffer,
+            details_buffer,
+            labels_buffer,
+            transfer_function_buffer,
+            fusion_volume_buffer,
+            fusion_transfer_function_buffer,
+            cinematic_buffer,
+            lighting_buffer,
+            gbuffer,
+            environment,
+            recompute_light: true,
+            light_stale: true,
+            last_light_state: Vec::new(),
+            frame_index: 0,
+            last_state: Vec::new(),
+        };
+        medical_pipeline.update(queue, camera);
+        Ok(medical_pipeline)
+    }
+
+    /// Uploads whatever changed of the settings, label styles and transfer functions, and
+    /// advances the cinematic accumulation (restarting it if the camera or any setting changed).
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        let (tf_min, tf_max) = self.transfer_function.range();
+        let (fusion_tf_min, fusion_tf_max) = self.fusion_transfer_function.range();
+        let axis = self.settings.slice_axis as usize;
+        let details = Details {
+            screen_width: self.texture.width() as f32,
+            screen_height: self.texture.height() as f32,
+            mode: self.settings.mode as u32,
+            step_size: self.settings.step_size,
+            window_center: self.settings.window_center,
+            window_width: self.settings.window_width,
+            tf_min,
+            tf_max,
+            slice_axis: self.settings.slice_axis as u32,
+            slice_index: self.settings.slice_index.min(self.dims[axis] - 1) as f32,
+            overlay_opacity: self.settings.overlay_opacity,
+            num_labels: self.labels.len() as u32,
+            blend_mode: self.settings.blend_mode as u32,
+            fusion_opacity: self.settings.fusion_opacity,
+            checker_size: self.settings.checker_size.max(1e-3),
+            has_fusion: self.fusion_grid.is_some() as u32,
+            fusion_tf_min,
+            fusion_tf_max,
+            fusion_value_min: self.fusion_value_range.0,
+            fusion_value_range: (self.fusion_value_range.1 - self.fusion_value_range.0).max(1e-6),
+        };
+
+        // Baking the lookup tables is the expensive part, only redo it after an edit
+        let transfer_function_changed = self.transfer_function != self.uploaded_transfer_function;
+        if transfer_function_changed {
+            queue.write_buffer(
+                &self.transfer_function_buffer,
+                0,
+                bytemuck::cast_slice(&self.transfer_function.bake()),
+            );
+            self.uploaded_transfer_function = self.transfer_function.clone();
+        }
+        let fusion_transfer_function_changed =
+            self.fusion_transfer_function != self.uploaded_fusion_transfer_function;
+        if fusion_transfer_function_changed {
+            queue.write_buffer(
+                &self.fusion_transfer_function_buffer,
+                0,
+                bytemuck::cast_slice(&self.fusion_transfer_function.bake()),
+            );
+            self.uploaded_fusion_transfer_function = self.fusion_transfer_function.clone();
+        }
+        let transfer_functions_changed = transfer_function_changed || fusion_transfer_function_changed;
+
+        // A singular registration (e.g. from a hand edited session) keeps the last valid one
+        let fusion_uniform = self.fusion_grid.and_then(|(dims, voxel_to_patient)| {
+            VolumeUniform::new(dims, self.fusion_registration * voxel_to_patient).ok()
+        });
+
+        let light_direction = Vector3::from(self.lighting.light_direction).normalize();
+        let lighting = LightingUniform {
+            light_direction: light_direction.into(),
+            shadows: self.lighting.shadows.clamp(0.0, 1.0),
+            grid_dims: self.light_grid,
+            occlusion: self.lighting.occlusion.clamp(0.0, 1.0),
+            ambient: self.lighting.ambient.clamp(0.0, 1.0),
+            ao_radius: self.lighting.ao_radius / self.min_spacing,
+            _pad: [0.0; 2],
+        };
+
+        let mut cinematic = CinematicUniform {
+            light_direction: light_direction.into(),
+            frame_index: 0,
+            light_color: self.cinematic.light_color,
+            max_bounces: self.cinematic.max_bounces,
+            sky_color: self.cinematic.sky_color,
+            density_scale: self.cinematic.density_scale,
+            ground_color: self.cinematic.ground_color,
+            anisotropy: self.cinematic.anisotropy.clamp(-0.99, 0.99),
+        };
+
+        // Everything else the classification depends on, which feeds both the light volume
+        // and the cinematic accumulation
+        let mut light_state = Vec::new();
+        light_state.extend_from_slice(bytemuck::bytes_of(&lighting));
+        light_state.extend_from_slice(bytemuck::cast_slice(&[tf_min, tf_max]));
+        light_state.extend_from_slice(bytemuck::cast_slice(&[
+            details.blend_mode as f32,
+            details.fusion_opacity,
+            details.checker_size,
+        ]));
+        light_state.extend_from_slice(bytemuck::cast_slice(self.labels.styles()));
+        if let Some(fusion_uniform) = &fusion_uniform {
+            light_state.extend_from_slice(bytemuck::bytes_of(fusion_uniform));
         }
+        let light_state_changed = light_state != self.last_light_state;
+        if light_state_changed {
+            queue.write_buffer(&self.lighting_buffer, 0, bytemuck::cast_slice(&[lighting]));
+            queue.write_buffer(&self.labels_buffer, 0, bytemuck::cast_slice(self.labels.styles()));
+            if let Some(fusion_uniform) = fusion_uniform {
+                queue.write_buffer(&self.fusion_volume_buffer, 0, bytemuck::cast_slice(&[fusion_uniform]));
+            }
+            self.last_light_state = light_state.clone();
+        }
+
+        // Edits outside of DVR are caught up on when switching to it
+        self.light_stale |= transfer_functions_changed || light_state_changed;
+        self.recompute_light = self.settings.mode == MedicalMode::Dvr && self.light_stale;
+        if self.recompute_light {
+            self.light_stale = false;
+        }
+
+        let mut state = light_state;
+        state.extend_from_slice(bytemuck::bytes_of(&details));
+        state.extend_from_slice(bytemuck::bytes_of(&cinematic));
+        state.extend_from_slice(bytemuck::bytes_of(&camera.uniform));
+        let restart = transfer_functions_changed || state != self.last_state;
+        if restart {
+            queue.write_buffer(&self.details_buffer, 0, bytemuck::cast_slice(&[details]));
+            self.last_state = state;
+            self.frame_index = 0;
+        } else {
+            self.frame_index += 1;
+        }
+        cinematic.frame_index = self.frame_index;
+
+        // The frame index seeds the path tracer, it changes every frame while accumulating
+        if restart || self.settings.mode == MedicalMode::Cinematic {
+            queue.write_buffer(&self.cinematic_buffer, 0, bytemuck::cast_slice(&[cinematic]));
+        }
+    }
+
+    /// Replaces the output and the per pixel buffers and binds the resized mesh pass targets.
+    /// The volumes, settings and transfer functions stay, the accumulation restarts.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        mesh_depth: &wgpu::TextureView,
+        mesh_color: &wgpu::TextureView,
+    ) {
+        (self.texture, self.accumulation_buffer, self.gbuffer) = MedicalPipeline::create_targets(device, width, height);
+        let texture_view = self.texture.create_view(&Default::default());
+        self.bind_group = MedicalPipeline::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            [
+                wgpu::BindingResource::TextureView(&texture_view),
+                wgpu::BindingResource::TextureView(&self.input_texture_view),
+                self.camera_buffer.as_entire_binding(),
+                self.details_buffer.as_entire_binding(),
+                wgpu::BindingResource::Sampler(&self.input_texture_sampler),
+                wgpu::BindingResource::TextureView(&self.label_texture_view),
+                self.labels_buffer.as_entire_binding(),
+                self.transfer_function_buffer.as_entire_binding(),
+                self.volume_buffer.as_entire_binding(),
+                wgpu::BindingResource::TextureView(&self.fusion_texture_view),
+                self.fusion_volume_buffer.as_entire_binding(),
+                self.fusion_transfer_function_buffer.as_entire_binding(),
+                self.accumulation_buffer.as_entire_binding(),
+                self.cinematic_buffer.as_entire_binding(),
+                self.light_volume_buffer.as_entire_binding(),
+                self.lighting_buffer.as_entire_binding(),
+                wgpu::BindingResource::TextureView(mesh_depth),
+                wgpu::BindingResource::TextureView(mesh_color),
+                self.gbuffer.as_entire_binding(),
+                wgpu::BindingResource::Sampler(&self.fusion_texture_sampler),
+            ],
+        );
+        self.last_state.clear();
+    }
+
+    // Output texture, accumulation buffer and G-buffer for a frame of the given size
+    fn create_targets(device: &wgpu::Device, width: u32, height: u32) -> (wgpu::Texture, wgpu::Buffer, wgpu::Buffer) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba16Float, // linear HDR, tonemapped for display
+            usage: wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::STORAGE_BINDING,
+            view_formats: Default::default(),
+        });
+
+        let accumulation_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("accumulation buffer"),
+            size: (width * height * 16) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let gbuffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("G-Buffer"),
+            size: (width * height) as wgpu::BufferAddress * GBUFFER_TEXEL_SIZE,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        (texture, accumulation_buffer, gbuffer)
+    }
+
+    // The resources in binding order
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        resources: [wgpu::BindingResource; 20],
+    ) -> wgpu::BindGroup {
+        let entries: Vec<_> = resources
+            .into_iter()
+            .enumerate()
+            .map(|(i, resource)| wgpu::BindGroupEntry {
+                binding: i as u32,
+                resource,
+            })
+            .collect();
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &entries,
+        })
+    }
+
+    /// Centre of the primary volume in patient coordinates (mm).
+    pub fn volume_center(&self) -> Vector3<f32> {
+        Volume::grid_center(self.dims, self.voxel_to_patient)
+    }
+
+    /// Radius of the sphere around `volume_center` that contains the whole volume.
+    pub fn volume_radius(&self) -> f32 {
+        let d = self.dims.map(|d| d as f32);
+        let diagonal = self.voxel_to_patient * Vector4::new(d[0], d[1], d[2], 0.0);
+        diagonal.truncate().magnitude() * 0.5
+    }
+
+    /// Number of frames accumulated by the cinematic renderer since the last reset.
+    pub fn accumulated_frames(&self) -> u32 {
+        self.frame_index + 1
+    }
+
+    /// Replaces the sky and ground gradient of the cinematic mode with an HDR map, `None`
+    /// goes back to the gradient.
+    pub fn set_environment(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, map: Option<&EnvironmentMap>) {
+        self.environment.set_map(device, queue, map);
+        self.last_state.clear();
+    }
+
+    pub fn environment_settings(&self) -> EnvironmentSettings {
+        self.environment.settings()
+    }
+
+    pub fn set_environment_settings(&mut self, queue: &wgpu::Queue, settings: EnvironmentSettings) {
+        self.environment.set_settings(queue, settings);
+        self.last_state.clear();
+    }
+
+    /// Albedo, depth and normal of the cinematic mode's primary surfaces, for the denoiser.
+    pub fn gbuffer(&self) -> &wgpu::Buffer {
+        &self.gbuffer
+    }
+
+    pub fn toggle_label(&mut self, label: u32) {
+        self.labels.toggle_visible(label);
     }
 
     pub fn pass(&self, encoder: &mut wgpu::CommandEncoder) {
@@ -233,11 +997,27 @@ impl MedicalPipeline {
             timestamp_writes: None,
         });
 
-        compute_pass.set_pipeline(&self.pipeline);
+        if self.recompute_light {
+            compute_pass.set_pipeline(&self.light_pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.set_bind_group(1, &self.environment.bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                self.light_grid[0].div_ceil(4),
+                self.light_grid[1].div_ceil(4),
+                self.light_grid[2].div_ceil(4),
+            );
+        }
+
+        if self.settings.mode == MedicalMode::Cinematic {
+            compute_pass.set_pipeline(&self.cinematic_pipeline);
+        } else {
+            compute_pass.set_pipeline(&self.pipeline);
+        }
         compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.environment.bind_group, &[]);
         compute_pass.dispatch_workgroups(
-            (self.texture.width() + 7) / 8,
-            (self.texture.height() + 7) / 8,
+            self.texture.width().div_ceil(8),
+            self.texture.height().div_ceil(8),
             1,
         );
     }
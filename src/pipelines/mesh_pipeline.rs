@@ -1,19 +1,52 @@
-use gltf::Gltf;
 use wgpu::util::DeviceExt;
 
 use crate::camera::Camera;
+use crate::mesh::gltf_scene::SceneMesh;
+use crate::mesh::{Material, TriangleMesh};
 
+const SAMPLE_COUNT: u32 = 4;
+
+/// Rasterizes triangle meshes in patient space into an offscreen colour target and the
+/// shared depth buffer, so the volume passes can composite against them.
 pub struct MeshPipeline {
     pipeline: wgpu::RenderPipeline,
+    material_bind_group_layout: wgpu::BindGroupLayout,
+    meshes: Vec<Mesh>,
+    overlays: Vec<Mesh>, // models placed by the scene file, see Scene::overlays
+    multisample_texture: wgpu::Texture,
+    texture: wgpu::Texture,
+}
+
+struct Mesh {
+    surface: TriangleMesh, // kept on the CPU for export and processing
+    material: Material,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
     num_indices: u32,
+    material_buffer: wgpu::Buffer,
+    material_bind_group: wgpu::BindGroup,
 }
 
-pub async fn load_binary(file_name: &str) -> Vec<u8> {
-    let path = std::path::Path::new("assets").join(file_name);
-    let data = std::fs::read(path).unwrap();
-    data
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    base_color: [f32; 4],
+    emissive: [f32; 3],
+    metallic: f32,
+    roughness: f32,
+    _pad: [f32; 3],
+}
+
+impl From<&Material> for MaterialUniform {
+    fn from(material: &Material) -> Self {
+        Self {
+            base_color: material.base_color,
+            emissive: material.emissive,
+            metallic: material.metallic,
+            roughness: material.roughness,
+            _pad: [0.0; 3],
+        }
+    }
 }
 
 #[repr(C)]
@@ -25,46 +58,13 @@ pub struct ModelVertex {
 }
 
 impl MeshPipeline {
-    pub fn new(surface_config: &wgpu::SurfaceConfiguration, device: &wgpu::Device, camera: &Camera) -> Self {
-        let gltf =
-            Gltf::open("/home/stephan/Dev/medical_wasm/assets/models/car.glb").unwrap();
-        let mesh = gltf.meshes().next().unwrap();
-        let primitive = mesh.primitives().next().unwrap();
-        let mut buffer_data = Vec::new();
-        for buffer in gltf.buffers() {
-            match buffer.source() {
-                gltf::buffer::Source::Bin => {
-                    if let Some(blob) = gltf.blob.as_deref() {
-                        buffer_data.push(blob.into());
-                    };
-                }
-                gltf::buffer::Source::Uri(uri) => {
-                    let bin = pollster::block_on(load_binary(uri));
-                    buffer_data.push(bin);
-                }
-            }
-        }
-        let reader = primitive.reader(|buffer| Some(&buffer_data[buffer.index()]));
-        let vertices: Vec<[f32; 3]> = reader.read_positions().unwrap().collect();
-        let normals: Vec<[f32; 3]> = reader.read_normals().unwrap().collect();
-
-        let model_vertices: Vec<ModelVertex> = vertices.into_iter().zip(normals).map(|(position, normal)| {
-            ModelVertex { position, normal }
-        }).collect();
-
-        let indices: Vec<u32> = reader.read_indices().unwrap().into_u32().collect();
-        let num_indices = indices.len() as u32;
+    pub fn create_view(&self) -> wgpu::TextureView {
+        self.texture.create_view(&Default::default())
+    }
 
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("vertex buffer"),
-            contents: bytemuck::cast_slice(&model_vertices),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("index buffer"),
-            contents: bytemuck::cast_slice(&indices),
-            usage: wgpu::BufferUsages::INDEX,
-        });
+    pub fn new(surface_config: &wgpu::SurfaceConfiguration, device: &wgpu::Device, camera: &Camera) -> Self {
+        let (multisample_texture, texture) =
+            MeshPipeline::create_targets(device, surface_config.width, surface_config.height);
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Shader"),
@@ -72,10 +72,24 @@ impl MeshPipeline {
         });
 
 
+        let material_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("material bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&camera.bind_group_layout],
+                bind_group_layouts: &[&camera.bind_group_layout, &material_bind_group_layout],
                 push_constant_ranges: &[],
             });
 
@@ -107,7 +121,7 @@ impl MeshPipeline {
                 module: &shader,
                 entry_point: Some("fs_main"),
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_config.format,
+                    format: texture.format(),
                     blend: Some(wgpu::BlendState {
                         color: wgpu::BlendComponent::REPLACE,
                         alpha: wgpu::BlendComponent::REPLACE,
@@ -120,19 +134,20 @@ impl MeshPipeline {
                 topology: wgpu::PrimitiveTopology::TriangleList,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
+                // Surfaces are often open or inconsistently wound, shade both sides
+                cull_mode: None,
                 polygon_mode: wgpu::PolygonMode::Fill,
                 unclipped_depth: false,
                 conservative: false,
             },
-            depth_stencil: Some(wgpu::DepthStencilState { 
+            depth_stencil: Some(wgpu::DepthStencilState {
                 format: wgpu::TextureFormat::Depth32Float,
                 depth_write_enabled: true,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default() }),
             multisample: wgpu::MultisampleState {
-                count: 4,
+                count: SAMPLE_COUNT,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -142,39 +157,109 @@ impl MeshPipeline {
 
         MeshPipeline {
             pipeline,
-            vertex_buffer,
-            index_buffer,
-            num_indices,
+            material_bind_group_layout,
+            meshes: Vec::new(),
+            overlays: Vec::new(),
+            multisample_texture,
+            texture,
         }
     }
 
+    /// Replaces the framebuffers, the meshes stay.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.multisample_texture, self.texture) = MeshPipeline::create_targets(device, width, height);
+    }
+
+    // Multisampled colour target and the texture it resolves to
+    fn create_targets(device: &wgpu::Device, width: u32, height: u32) -> (wgpu::Texture, wgpu::Texture) {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let multisample_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Mesh Multisampled Framebuffer"),
+            size,
+            mip_level_count: 1,
+            sample_count: SAMPLE_COUNT,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Mesh Framebuffer"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        (multisample_texture, texture)
+    }
+
+    /// Uploads a triangle mesh in patient space (mm), returns its index.
+    pub fn add_mesh(&mut self, device: &wgpu::Device, surface: TriangleMesh, material: Material) -> usize {
+        let mesh = Mesh::new(device, &self.material_bind_group_layout, surface, material);
+        self.meshes.push(mesh);
+        self.meshes.len() - 1
+    }
+
+    /// Swaps the geometry of a mesh, e.g. after smoothing or decimation.
+    pub fn replace_mesh(&mut self, device: &wgpu::Device, index: usize, surface: TriangleMesh) {
+        let material = self.meshes[index].material;
+        self.meshes[index] = Mesh::new(device, &self.material_bind_group_layout, surface, material);
+    }
+
+    pub fn set_material(&mut self, queue: &wgpu::Queue, index: usize, material: Material) {
+        let mesh = &mut self.meshes[index];
+        mesh.material = material;
+        queue.write_buffer(&mesh.material_buffer, 0, bytemuck::cast_slice(&[MaterialUniform::from(&material)]));
+    }
+
+    pub fn mesh(&self, index: usize) -> Option<&TriangleMesh> {
+        self.meshes.get(index).map(|mesh| &mesh.surface)
+    }
+
+    pub fn mesh_count(&self) -> usize {
+        self.meshes.len()
+    }
+
+    /// Replaces the glTF primitives shown with the volume, the meshes added one by one stay.
+    pub fn set_overlays(&mut self, device: &wgpu::Device, overlays: Vec<SceneMesh>) {
+        self.overlays = overlays
+            .into_iter()
+            .map(|overlay| Mesh::new(device, &self.material_bind_group_layout, overlay.mesh, overlay.material))
+            .collect();
+    }
+
+    pub fn clear(&mut self) {
+        self.meshes.clear();
+    }
+
+    /// Always runs, even without meshes, so the depth buffer is cleared for the volume passes.
     pub fn pass(
         &self,
-        output_view: &wgpu::TextureView,
-        multisample_view: &wgpu::TextureView,
         depthbuffer_view: &wgpu::TextureView,
         encoder: &mut wgpu::CommandEncoder,
         camera: &Camera
     ) {
+        let multisample_view = self.multisample_texture.create_view(&Default::default());
+        let output_view = self.create_view();
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                 view: &multisample_view,
                 resolve_target: Some(&output_view),
-                // view: &output_view,
-                // resolve_target: None,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color {
-                        r: 0.1,
-                        g: 0.2,
-                        b: 0.3,
-                        a: 1.0,
-                    }),
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
                     store: wgpu::StoreOp::Store,
                 },
             })],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &depthbuffer_view,
+                view: depthbuffer_view,
                 depth_ops: Some(wgpu::Operations{
                     load: wgpu::LoadOp::Clear(1.0),
                     store: wgpu::StoreOp::Store
@@ -187,8 +272,55 @@ impl MeshPipeline {
 
         render_pass.set_pipeline(&self.pipeline);
         render_pass.set_bind_group(0, &camera.bind_group, &[]);
-        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-        render_pass.draw_indexed(0..self.num_indices, 0, 0..1)
+        for mesh in self.meshes.iter().chain(&self.overlays).filter(|mesh| mesh.num_indices > 0) {
+            render_pass.set_bind_group(1, &mesh.material_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
+        }
+    }
+}
+
+impl Mesh {
+    fn new(
+        device: &wgpu::Device,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        surface: TriangleMesh,
+        material: Material,
+    ) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("vertex buffer"),
+            contents: bytemuck::cast_slice(&surface.vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("index buffer"),
+            contents: bytemuck::cast_slice(&surface.indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let material_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("material buffer"),
+            contents: bytemuck::cast_slice(&[MaterialUniform::from(&material)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let material_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("material bind group"),
+            layout: material_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: material_buffer.as_entire_binding(),
+            }],
+        });
+
+        Mesh {
+            num_indices: surface.indices.len() as u32,
+            surface,
+            material,
+            vertex_buffer,
+            index_buffer,
+            material_buffer,
+            material_bind_group,
+        }
     }
 }
@@ -1,20 +1,48 @@
-use medical_pipeline::MedicalPipeline;
+use std::path::Path;
+
+use denoise_pipeline::{DenoisePipeline, DenoiseSettings};
+use medical_pipeline::{MedicalMode, MedicalPipeline};
+use mesh_pipeline::MeshPipeline;
 use raytrace_pipeline::RaytracePipeline;
-use sampletexture_pipeline::SampleTexturePipeline;
-use wgpu::wgc::ray_tracing;
+use sampletexture_pipeline::{SampleTexturePipeline, ToneMapping, Tonemapper};
 
 use crate::camera::Camera;
+use crate::mesh::decimate::decimate;
+use crate::mesh::smooth::{smooth, Smoothing};
+use crate::mesh::{marching_cubes, Material, TriangleMesh};
+use crate::volume::Volume;
 
+pub mod denoise_pipeline;
 pub mod medical_pipeline;
 pub mod mesh_pipeline;
 pub mod raytrace_pipeline;
 pub mod sampletexture_pipeline;
 pub mod triangle_pipeline;
 
+/// Cortical bone in Hounsfield units, the surface shown of a scan without segmentation.
+pub const BONE_ISO_VALUE: f32 = 300.0;
+
+// Extracted surfaces are decimated to about this many triangles
+const MAX_SURFACE_TRIANGLES: usize = 200_000;
+
+/// What the mesh pass shows of the volume: where the scan crosses a value, or the boundary of
+/// one structure of the segmentation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SurfaceSource {
+    IsoValue(f32),
+    Label(u32),
+}
+
 pub struct Pipelines {
-    raytrace_pipeline: RaytracePipeline,
-    // medical_pipeline: MedicalPipeline,
+    pub raytrace_pipeline: RaytracePipeline,
+    pub mesh_pipeline: MeshPipeline,
+    pub medical_pipeline: Option<MedicalPipeline>,
+    denoise_pipeline: DenoisePipeline,
     sample_pipeline: SampleTexturePipeline,
+    // Kept on the CPU to extract surfaces from
+    volume: Option<Volume>,
+    label_volume: Option<Volume>,
+    surface: Option<(SurfaceSource, usize)>, // and its index in the mesh pipeline
 }
 
 impl Pipelines {
@@ -23,29 +51,213 @@ impl Pipelines {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         camera: &Camera,
+        depthbuffer_view: &wgpu::TextureView,
     ) -> Self {
-        let raytrace_pipeline = RaytracePipeline::new(&surface_config, &device, &camera);
-        // let medical_pipeline = MedicalPipeline::new(&surface_config, &device, &queue, &camera);
+        let raytrace_pipeline = RaytracePipeline::new(surface_config, device, queue, camera);
+        let mesh_pipeline = MeshPipeline::new(surface_config, device, camera);
+
+        // The medical view needs the LCTSC scan on disk, fall back to the ray tracer without it
+        let assets = Path::new("assets");
+        let ct_volume = Volume::from_nifti(assets.join(medical_pipeline::LCTSC_CT_PATH));
+        let (medical_pipeline, volume, label_volume) = match ct_volume {
+            Ok(volume) => {
+                // The segmentation is optional, without it the scan is shown unlabelled
+                let label_volume = Volume::from_nifti(assets.join(medical_pipeline::LCTSC_LABEL_PATH))
+                    .and_then(|label_volume| volume.check_grid(&label_volume).map(|()| label_volume))
+                    .map_err(|e| println!("{e}, showing the scan without labels"))
+                    .ok();
+                let fusion_volume = Volume::from_nifti(assets.join(medical_pipeline::LCTSC_FUSION_PATH))
+                    .map_err(|e| println!("{e}, showing the scan without fusion"))
+                    .ok();
+                match MedicalPipeline::new(
+                    surface_config,
+                    device,
+                    queue,
+                    camera,
+                    &volume,
+                    label_volume.as_ref(),
+                    fusion_volume.as_ref(),
+                    depthbuffer_view,
+                    &mesh_pipeline.create_view(),
+                ) {
+                    Ok(medical_pipeline) => (Some(medical_pipeline), Some(volume), label_volume),
+                    Err(e) => {
+                        println!("{e}, using the built-in scene");
+                        (None, None, None)
+                    }
+                }
+            }
+            Err(e) => {
+                println!("{e}, using the built-in scene");
+                (None, None, None)
+            }
+        };
+
+        // Volume renderings are display ready, the path tracer needs its highlights compressed
+        let (traced_view, gbuffer, tone_mapping) = match &medical_pipeline {
+            Some(medical_pipeline) => (
+                medical_pipeline.create_view(),
+                medical_pipeline.gbuffer(),
+                ToneMapping::default(),
+            ),
+            None => (
+                raytrace_pipeline.create_view(),
+                raytrace_pipeline.gbuffer(),
+                ToneMapping { operator: Tonemapper::Aces, exposure: 0.0 },
+            ),
+        };
+        let denoise_pipeline = DenoisePipeline::new(surface_config, device, &traced_view, gbuffer);
         let sample_pipeline =
-            SampleTexturePipeline::new(&surface_config, &device, raytrace_pipeline.create_view());
+            SampleTexturePipeline::new(surface_config, device, denoise_pipeline.create_view(), tone_mapping);
 
-        Pipelines {
+        let mut pipelines = Pipelines {
             raytrace_pipeline,
-            // medical_pipeline,
+            mesh_pipeline,
+            medical_pipeline,
+            denoise_pipeline,
             sample_pipeline,
+            volume,
+            label_volume,
+            surface: None,
+        };
+
+        // The first structure of the segmentation, or the bones without one
+        let source = match &pipelines.label_volume {
+            Some(_) => SurfaceSource::Label(1),
+            None => SurfaceSource::IsoValue(BONE_ISO_VALUE),
+        };
+        pipelines.set_surface(device, queue, source);
+        pipelines
+    }
+
+    pub fn surface_source(&self) -> Option<SurfaceSource> {
+        self.surface.map(|(source, _)| source)
+    }
+
+    /// The extracted surface as shown, after smoothing and decimation.
+    pub fn surface_mesh(&self) -> Option<&TriangleMesh> {
+        self.surface.and_then(|(_, index)| self.mesh_pipeline.mesh(index))
+    }
+
+    /// Extracts a surface of the loaded volume and shows it in place of the previous one.
+    /// Does nothing without a volume, or for a label without a segmentation.
+    pub fn set_surface(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, source: SurfaceSource) {
+        let (Some(volume), Some(medical_pipeline)) = (&self.volume, &self.medical_pipeline) else {
+            return;
+        };
+        let (mut mesh, material) = match source {
+            SurfaceSource::IsoValue(iso) => (marching_cubes::isosurface(volume, iso), Material::default()),
+            SurfaceSource::Label(label) => {
+                let (Some(label_volume), Some(style)) =
+                    (&self.label_volume, medical_pipeline.labels.styles().get(label as usize))
+                else {
+                    return;
+                };
+                let [r, g, b, _] = style.color;
+                // Coloured per vertex as well, so an exported surface keeps the label colour
+                let mut mesh = marching_cubes::label_surface(label_volume, label);
+                mesh.set_color([r, g, b]);
+                (mesh, Material::from_color([r, g, b]))
+            }
+        };
+        // Marching cubes follows the voxel grid in steps and makes far more triangles than
+        // the mesh pass needs
+        smooth(&mut mesh, &Smoothing::taubin(10));
+        let mesh = decimate(&mesh, MAX_SURFACE_TRIANGLES);
+        println!("{source:?}: {} triangles", mesh.triangle_count());
+
+        let index = match self.surface {
+            Some((_, index)) => {
+                self.mesh_pipeline.replace_mesh(device, index, mesh);
+                self.mesh_pipeline.set_material(queue, index, material);
+                index
+            }
+            None => self.mesh_pipeline.add_mesh(device, mesh, material),
+        };
+        self.surface = Some((source, index));
+    }
+
+    /// Rebuilds everything that depends on the window size. Scenes, volumes, meshes and
+    /// settings stay, accumulated frames start over.
+    pub fn resize(
+        &mut self,
+        surface_config: &wgpu::SurfaceConfiguration,
+        device: &wgpu::Device,
+        depthbuffer_view: &wgpu::TextureView,
+    ) {
+        let (width, height) = (surface_config.width, surface_config.height);
+        self.raytrace_pipeline.resize(device, width, height);
+        self.mesh_pipeline.resize(device, width, height);
+        if let Some(medical_pipeline) = &mut self.medical_pipeline {
+            medical_pipeline.resize(device, width, height, depthbuffer_view, &self.mesh_pipeline.create_view());
         }
+
+        let (traced_view, gbuffer) = match &self.medical_pipeline {
+            Some(medical_pipeline) => (medical_pipeline.create_view(), medical_pipeline.gbuffer()),
+            None => (self.raytrace_pipeline.create_view(), self.raytrace_pipeline.gbuffer()),
+        };
+        let denoise_settings = self.denoise_pipeline.settings();
+        self.denoise_pipeline = DenoisePipeline::new(surface_config, device, &traced_view, gbuffer);
+        self.denoise_pipeline.set_settings(denoise_settings);
+        self.sample_pipeline = SampleTexturePipeline::new(
+            surface_config,
+            device,
+            self.denoise_pipeline.create_view(),
+            self.sample_pipeline.tone_mapping(),
+        );
+    }
+
+    pub fn tone_mapping(&self) -> ToneMapping {
+        self.sample_pipeline.tone_mapping()
+    }
+
+    pub fn set_tone_mapping(&mut self, queue: &wgpu::Queue, tone_mapping: ToneMapping) {
+        self.sample_pipeline.set_tone_mapping(queue, tone_mapping);
+    }
+
+    pub fn denoise_settings(&self) -> DenoiseSettings {
+        self.denoise_pipeline.settings()
+    }
+
+    pub fn set_denoise_settings(&mut self, settings: DenoiseSettings) {
+        self.denoise_pipeline.set_settings(settings);
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        // Only the path tracers are noisy, DVR, MIP and slices pass through the denoiser
+        let (noisy, frames) = match &mut self.medical_pipeline {
+            Some(medical_pipeline) => {
+                medical_pipeline.update(queue, camera);
+                (
+                    medical_pipeline.settings.mode == MedicalMode::Cinematic,
+                    medical_pipeline.accumulated_frames(),
+                )
+            }
+            None => {
+                self.raytrace_pipeline.update(queue, camera);
+                (true, self.raytrace_pipeline.accumulated_frames())
+            }
+        };
+        self.denoise_pipeline.update(queue, noisy, frames);
     }
 
     pub fn render(
         &self,
         device: &wgpu::Device,
         output_view: &wgpu::TextureView,
-        _multisample_framebuffer_view: &wgpu::TextureView,
-        _depthbuffer_view: &wgpu::TextureView,
-        encoder: &mut wgpu::CommandEncoder, // _camera: &Camera,
+        depthbuffer_view: &wgpu::TextureView,
+        encoder: &mut wgpu::CommandEncoder,
+        camera: &Camera,
     ) {
-        self.raytrace_pipeline.pass(encoder);
-        // self.medical_pipeline.pass(encoder);
+        match &self.medical_pipeline {
+            Some(medical_pipeline) => {
+                // Meshes first, the volume rays terminate at their depth
+                self.mesh_pipeline.pass(depthbuffer_view, encoder, camera);
+                medical_pipeline.pass(encoder);
+            }
+            None => self.raytrace_pipeline.pass(encoder),
+        }
+        self.denoise_pipeline.pass(encoder);
         self.sample_pipeline.pass(device, output_view, encoder);
     }
 }
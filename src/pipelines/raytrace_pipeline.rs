@@ -1,10 +1,34 @@
-use crate::camera::Camera;
+use crate::camera::{Camera, CameraUniform};
+use crate::environment::{Environment, EnvironmentMap, EnvironmentSettings};
+use crate::mesh::bvh::Bvh;
+use crate::mesh::gltf_scene::{GltfScene, SceneMesh};
+use crate::pipelines::denoise_pipeline::GBUFFER_TEXEL_SIZE;
+use crate::scene::{self, Scene, SceneError};
+use serde::{Deserialize, Serialize};
 use wgpu::util::DeviceExt;
 
 pub struct RaytracePipeline {
     pub pipeline: wgpu::ComputePipeline,
     pub bind_group: wgpu::BindGroup,
     pub texture: wgpu::Texture,
+    bind_group_layout: wgpu::BindGroupLayout,
+    camera_buffer: wgpu::Buffer,
+    spheres_buffer: wgpu::Buffer,
+    details_buffer: wgpu::Buffer,
+    bvh_nodes_buffer: wgpu::Buffer,
+    triangles_buffer: wgpu::Buffer,
+    mesh_materials_buffer: wgpu::Buffer,
+    accumulation_buffer: wgpu::Buffer,
+    gbuffer: wgpu::Buffer,
+    environment: Environment, // bind group 1
+    details: Details,
+    last_camera: CameraUniform, // accumulation restarts when this changes
+    reset_accumulation: bool,
+
+    // CPU side of the scene, uploaded again whenever it changes
+    spheres: Vec<scene::Sphere>,
+    lights: Vec<scene::Light>,
+    meshes: Vec<SceneMesh>,
 }
 
 #[repr(C)]
@@ -13,7 +37,9 @@ struct Material {
     albedo: [f32; 4],
     emission: [f32; 4],
     roughness: f32,
-    _pad: [f32; 3],
+    metallic: f32,
+    transmission: f32,
+    ior: f32,
 }
 
 #[repr(C)]
@@ -24,6 +50,62 @@ struct Sphere {
     material: Material,
 }
 
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Details {
+    screen_width: f32,
+    screen_height: f32,
+    sphere_count: u32, // the spheres buffer only grows, it can hold more than this
+    first_light: u32,  // emissive spheres are sorted to the end for light sampling
+    max_bounces: u32,
+    samples_per_pixel: u32,
+    antialias: u32,
+    frame_index: u32,
+}
+
+/// Quality knobs of the path tracer, trading frame time for noise.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TraceSettings {
+    pub max_bounces: u32,       // paths end earlier through Russian roulette
+    pub samples_per_pixel: u32,
+    pub antialias: bool,        // jitters the samples within the pixel
+}
+
+impl Default for TraceSettings {
+    fn default() -> Self {
+        Self {
+            max_bounces: 4,
+            samples_per_pixel: 1,
+            antialias: true,
+        }
+    }
+}
+
+impl From<&scene::Sphere> for Sphere {
+    fn from(sphere: &scene::Sphere) -> Self {
+        Sphere {
+            pos: sphere.center,
+            radius: sphere.radius,
+            material: Material::from(&sphere.material),
+        }
+    }
+}
+
+impl From<&crate::mesh::Material> for Material {
+    fn from(material: &crate::mesh::Material) -> Self {
+        let e = material.emissive;
+        Material {
+            albedo: material.base_color,
+            emission: [e[0], e[1], e[2], 0.0],
+            roughness: material.roughness,
+            metallic: material.metallic,
+            transmission: material.transmission,
+            ior: material.ior,
+        }
+    }
+}
+
 impl RaytracePipeline {
     pub fn create_view(&self) -> wgpu::TextureView {
         self.texture.create_view(&Default::default())
@@ -32,80 +114,47 @@ impl RaytracePipeline {
     pub fn new(
         surface_config: &wgpu::SurfaceConfiguration,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         camera: &Camera,
     ) -> Self {
-        let spheres = vec![
-            Sphere {
-                pos: [0.2, 0.0, 0.0],
-                radius: 0.3,
-                material: Material {
-                    albedo: [1.0, 0.0, 0.0, 1.0],
-                    emission: [0.0, 0.0, 0.0, 0.0],
-                    roughness: 0.0,
-                    _pad: [0.0; 3],
-                },
-            },
-            Sphere {
-                pos: [1.0, 0.0, 0.0],
-                radius: 0.5,
-                material: Material {
-                    albedo: [0.0, 0.0, 1.0, 1.0],
-                    emission: [0.0, 0.0, 0.0, 0.0],
-                    roughness: 0.0,
-                    _pad: [0.0; 3],
-                },
-            },
-            Sphere {
-                pos: [0.0, -25.5, -1.0],
-                radius: 25.0,
-                material: Material {
-                    albedo: [0.0, 1.0, 0.0, 1.0],
-                    emission: [0.0, 0.0, 0.0, 0.0],
-                    roughness: 0.0,
-                    _pad: [0.0; 3],
-                },
-            },
-        ];
+        let spheres_buffer = RaytracePipeline::create_spheres_buffer(device, &[]);
 
-        let spheres_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Spheres Buffer"),
-            contents: bytemuck::cast_slice(&spheres),
-            usage: wgpu::BufferUsages::STORAGE,
-        });
-
-        let details = vec![surface_config.width as f32, surface_config.height as f32];
+        let settings = TraceSettings::default();
+        let details = Details {
+            screen_width: surface_config.width as f32,
+            screen_height: surface_config.height as f32,
+            sphere_count: 0,
+            first_light: 0,
+            max_bounces: settings.max_bounces,
+            samples_per_pixel: settings.samples_per_pixel,
+            antialias: settings.antialias as u32,
+            frame_index: 0,
+        };
         let details_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("details buffer"),
-            contents: bytemuck::cast_slice(&details),
+            contents: bytemuck::cast_slice(&[details]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let shader_string = include_str!("shaders/raytrace.wgsl");
+        let (bvh_nodes_buffer, triangles_buffer, mesh_materials_buffer) =
+            RaytracePipeline::create_mesh_buffers(device, &[]);
+
+        let (texture, accumulation_buffer, gbuffer) =
+            RaytracePipeline::create_targets(device, surface_config.width, surface_config.height);
+
+        let environment = Environment::new(device, queue);
+
+        let shader_string = format!(
+            "{}\n{}",
+            include_str!("shaders/raytrace.wgsl"),
+            include_str!("shaders/environment.wgsl")
+        );
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Shader_ray"),
             source: wgpu::ShaderSource::Wgsl(shader_string.into()),
         });
 
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: None,
-            size: wgpu::Extent3d {
-                width: surface_config.width,
-                height: surface_config.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::COPY_DST
-                | wgpu::TextureUsages::TEXTURE_BINDING
-                | wgpu::TextureUsages::STORAGE_BINDING,
-            view_formats: Default::default(),
-        });
-
-        let texture_view = texture.create_view(&Default::default());
-
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("ray bind group layout"),
             entries: &[
@@ -149,35 +198,78 @@ impl RaytracePipeline {
                     },
                     count: None,
                 },
-            ],
-        });
-
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: camera.buffer.as_entire_binding(),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: spheres_buffer.as_entire_binding(),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: details_buffer.as_entire_binding(),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
             ],
         });
 
+        let bind_group = RaytracePipeline::create_bind_group(
+            device,
+            &bind_group_layout,
+            &texture,
+            [
+                &camera.buffer,
+                &spheres_buffer,
+                &details_buffer,
+                &bvh_nodes_buffer,
+                &triangles_buffer,
+                &mesh_materials_buffer,
+                &accumulation_buffer,
+                &gbuffer,
+            ],
+        );
+
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Compute Pipeline Layout"),
-            bind_group_layouts: &[&bind_group_layout],
+            bind_group_layouts: &[&bind_group_layout, &environment.bind_group_layout],
             push_constant_ranges: &[],
         });
 
@@ -194,7 +286,314 @@ impl RaytracePipeline {
             pipeline,
             bind_group,
             texture,
+            bind_group_layout,
+            camera_buffer: camera.buffer.clone(),
+            spheres_buffer,
+            details_buffer,
+            bvh_nodes_buffer,
+            triangles_buffer,
+            mesh_materials_buffer,
+            accumulation_buffer,
+            gbuffer,
+            environment,
+            details,
+            last_camera: camera.uniform,
+            reset_accumulation: true,
+            spheres: Vec::new(),
+            lights: Vec::new(),
+            meshes: Vec::new(),
+        }
+    }
+
+    /// Replaces the objects with the contents of a scene file. The meshes are loaded first, so
+    /// a scene that fails to load leaves the current one untouched. The environment is shared
+    /// with the volume, see Renderer::set_scene().
+    pub fn set_scene(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, scene: &Scene) -> Result<(), SceneError> {
+        self.meshes = scene.load_meshes()?;
+        self.spheres = scene.spheres.clone();
+        self.lights = scene.lights.clone();
+        self.upload_spheres(device, queue);
+        self.upload_meshes(device);
+        self.reset_accumulation();
+        Ok(())
+    }
+
+    /// Lights the scene with an HDR map instead of the white sky, `None` goes back to the sky.
+    pub fn set_environment(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, map: Option<&EnvironmentMap>) {
+        self.environment.set_map(device, queue, map);
+        self.reset_accumulation();
+    }
+
+    pub fn environment_settings(&self) -> EnvironmentSettings {
+        self.environment.settings()
+    }
+
+    pub fn set_environment_settings(&mut self, queue: &wgpu::Queue, settings: EnvironmentSettings) {
+        self.environment.set_settings(queue, settings);
+        self.reset_accumulation();
+    }
+
+    pub fn settings(&self) -> TraceSettings {
+        TraceSettings {
+            max_bounces: self.details.max_bounces,
+            samples_per_pixel: self.details.samples_per_pixel,
+            antialias: self.details.antialias != 0,
+        }
+    }
+
+    /// Takes effect with the next `update`, which uploads the details uniform.
+    pub fn set_settings(&mut self, settings: TraceSettings) {
+        self.details.max_bounces = settings.max_bounces;
+        self.details.samples_per_pixel = settings.samples_per_pixel.max(1);
+        self.details.antialias = settings.antialias as u32;
+        self.reset_accumulation();
+    }
+
+    /// Advances the accumulation by a frame, or restarts it when the camera moved.
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        if self.reset_accumulation || bytemuck::bytes_of(&camera.uniform) != bytemuck::bytes_of(&self.last_camera) {
+            self.last_camera = camera.uniform;
+            self.reset_accumulation = false;
+            self.details.frame_index = 0;
+        } else {
+            self.details.frame_index = self.details.frame_index.saturating_add(1);
+        }
+        queue.write_buffer(&self.details_buffer, 0, bytemuck::cast_slice(&[self.details]));
+    }
+
+    /// Number of frames averaged since the last reset.
+    pub fn accumulated_frames(&self) -> u32 {
+        self.details.frame_index + 1
+    }
+
+    /// Albedo, depth and normal of the primary hits, for the denoiser.
+    pub fn gbuffer(&self) -> &wgpu::Buffer {
+        &self.gbuffer
+    }
+
+    /// Throws away the accumulated frames, e.g. after editing the scene.
+    pub fn reset_accumulation(&mut self) {
+        self.reset_accumulation = true;
+    }
+
+    pub fn spheres(&self) -> &[scene::Sphere] {
+        &self.spheres
+    }
+
+    /// Returns the index of the new sphere.
+    pub fn add_sphere(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, sphere: scene::Sphere) -> usize {
+        self.spheres.push(sphere);
+        self.upload_spheres(device, queue);
+        self.spheres.len() - 1
+    }
+
+    pub fn set_sphere(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, index: usize, sphere: scene::Sphere) {
+        self.spheres[index] = sphere;
+        self.upload_spheres(device, queue);
+    }
+
+    pub fn remove_sphere(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, index: usize) -> scene::Sphere {
+        let sphere = self.spheres.remove(index);
+        self.upload_spheres(device, queue);
+        sphere
+    }
+
+    pub fn lights(&self) -> &[scene::Light] {
+        &self.lights
+    }
+
+    pub fn add_light(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, light: scene::Light) -> usize {
+        self.lights.push(light);
+        self.upload_spheres(device, queue);
+        self.lights.len() - 1
+    }
+
+    pub fn remove_light(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, index: usize) -> scene::Light {
+        let light = self.lights.remove(index);
+        self.upload_spheres(device, queue);
+        light
+    }
+
+    pub fn meshes(&self) -> &[SceneMesh] {
+        &self.meshes
+    }
+
+    /// Adds the primitives of a glTF scene and rebuilds the BVH, returns the index of the first.
+    pub fn add_meshes(&mut self, device: &wgpu::Device, scene: GltfScene) -> usize {
+        let first = self.meshes.len();
+        self.meshes.extend(scene.meshes);
+        self.upload_meshes(device);
+        first
+    }
+
+    pub fn remove_mesh(&mut self, device: &wgpu::Device, index: usize) -> SceneMesh {
+        let mesh = self.meshes.remove(index);
+        self.upload_meshes(device);
+        mesh
+    }
+
+    // Lights are traced as emissive spheres, which go after the others for next event estimation
+    fn upload_spheres(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let mut spheres: Vec<Sphere> = self
+            .spheres
+            .iter()
+            .copied()
+            .chain(self.lights.iter().map(scene::Light::sphere))
+            .map(|sphere| Sphere::from(&sphere))
+            .collect();
+        spheres.sort_by_key(|sphere| sphere.material.emission[..3].iter().any(|e| *e > 0.0));
+
+        let size = std::mem::size_of_val(spheres.as_slice()) as wgpu::BufferAddress;
+        if size > self.spheres_buffer.size() {
+            self.spheres_buffer = RaytracePipeline::create_spheres_buffer(device, &spheres);
+            self.rebuild_bind_group(device);
+        } else if !spheres.is_empty() {
+            queue.write_buffer(&self.spheres_buffer, 0, bytemuck::cast_slice(&spheres));
+        }
+
+        self.reset_accumulation();
+        self.details.sphere_count = spheres.len() as u32;
+        self.details.first_light = spheres
+            .iter()
+            .position(|sphere| sphere.material.emission[..3].iter().any(|e| *e > 0.0))
+            .unwrap_or(spheres.len()) as u32;
+    }
+
+    fn upload_meshes(&mut self, device: &wgpu::Device) {
+        let (bvh_nodes_buffer, triangles_buffer, mesh_materials_buffer) =
+            RaytracePipeline::create_mesh_buffers(device, &self.meshes);
+        self.bvh_nodes_buffer = bvh_nodes_buffer;
+        self.triangles_buffer = triangles_buffer;
+        self.mesh_materials_buffer = mesh_materials_buffer;
+        self.rebuild_bind_group(device);
+        self.reset_accumulation();
+    }
+
+    /// Replaces the output and the per pixel buffers, the scene and settings stay.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.texture, self.accumulation_buffer, self.gbuffer) = RaytracePipeline::create_targets(device, width, height);
+        self.details.screen_width = width as f32;
+        self.details.screen_height = height as f32;
+        self.rebuild_bind_group(device);
+        self.reset_accumulation();
+    }
+
+    // Output texture, accumulation buffer and G-buffer for a frame of the given size
+    fn create_targets(device: &wgpu::Device, width: u32, height: u32) -> (wgpu::Texture, wgpu::Buffer, wgpu::Buffer) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba16Float, // linear HDR, tonemapped for display
+            usage: wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::STORAGE_BINDING,
+            view_formats: Default::default(),
+        });
+
+        let accumulation_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Accumulation Buffer"),
+            size: (width * height) as wgpu::BufferAddress * 16,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let gbuffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("G-Buffer"),
+            size: (width * height) as wgpu::BufferAddress * GBUFFER_TEXEL_SIZE,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        (texture, accumulation_buffer, gbuffer)
+    }
+
+    fn rebuild_bind_group(&mut self, device: &wgpu::Device) {
+        self.bind_group = RaytracePipeline::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.texture,
+            [
+                &self.camera_buffer,
+                &self.spheres_buffer,
+                &self.details_buffer,
+                &self.bvh_nodes_buffer,
+                &self.triangles_buffer,
+                &self.mesh_materials_buffer,
+                &self.accumulation_buffer,
+                &self.gbuffer,
+            ],
+        );
+    }
+
+    // Room for the next power of two of spheres, storage buffers can't be empty
+    fn create_spheres_buffer(device: &wgpu::Device, spheres: &[Sphere]) -> wgpu::Buffer {
+        let capacity = spheres.len().max(4).next_power_of_two();
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Spheres Buffer"),
+            size: (capacity * std::mem::size_of::<Sphere>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: true,
+        });
+        buffer.slice(..).get_mapped_range_mut()[..std::mem::size_of_val(spheres)]
+            .copy_from_slice(bytemuck::cast_slice(spheres));
+        buffer.unmap();
+        buffer
+    }
+
+    // BVH nodes, triangles and one material per scene mesh
+    fn create_mesh_buffers(device: &wgpu::Device, meshes: &[SceneMesh]) -> (wgpu::Buffer, wgpu::Buffer, wgpu::Buffer) {
+        let bvh = Bvh::build(meshes.iter().enumerate().map(|(i, m)| (&m.mesh, i as u32)));
+        let mut materials: Vec<Material> = meshes.iter().map(|m| Material::from(&m.material)).collect();
+        if materials.is_empty() {
+            materials.push(Material::from(&crate::mesh::Material::default()));
         }
+
+        let bvh_nodes_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("BVH Nodes Buffer"),
+            contents: bytemuck::cast_slice(&bvh.gpu_nodes()),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let triangles_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Triangles Buffer"),
+            contents: bytemuck::cast_slice(&bvh.gpu_triangles()),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let mesh_materials_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mesh Materials Buffer"),
+            contents: bytemuck::cast_slice(&materials),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        (bvh_nodes_buffer, triangles_buffer, mesh_materials_buffer)
+    }
+
+    // Binding 0 is the output texture, the buffers follow in binding order
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &wgpu::Texture,
+        buffers: [&wgpu::Buffer; 8],
+    ) -> wgpu::BindGroup {
+        let texture_view = texture.create_view(&Default::default());
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(&texture_view),
+        }];
+        entries.extend(buffers.iter().enumerate().map(|(i, buffer)| wgpu::BindGroupEntry {
+            binding: i as u32 + 1,
+            resource: buffer.as_entire_binding(),
+        }));
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &entries,
+        })
     }
 
     pub fn pass(&self, encoder: &mut wgpu::CommandEncoder) {
@@ -205,9 +604,10 @@ impl RaytracePipeline {
 
         compute_pass.set_pipeline(&self.pipeline);
         compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.environment.bind_group, &[]);
         compute_pass.dispatch_workgroups(
-            (self.texture.width() + 7) / 8,
-            (self.texture.height() + 7) / 8,
+            self.texture.width().div_ceil(8),
+            self.texture.height().div_ceil(8),
             1,
         );
     }
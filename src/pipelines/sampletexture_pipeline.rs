@@ -1,9 +1,48 @@
 use wgpu::util::DeviceExt;
 use crate::{quad::Quad, vertex::Vertex};
+use serde::{Deserialize, Serialize};
 
 pub struct SampleTexturePipeline {
     pipeline: wgpu::RenderPipeline,
     bind_group: wgpu::BindGroup,
+    tone_mapping: ToneMapping,
+    tone_mapping_buffer: wgpu::Buffer,
+    encode_srgb: bool,
+}
+
+/// Maps scene radiance to display values.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Tonemapper {
+    Clamp = 0,
+    Reinhard = 1,
+    Aces = 2,
+    Filmic = 3,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToneMapping {
+    pub operator: Tonemapper,
+    pub exposure: f32, // stops, 0 leaves the input as is
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: Tonemapper::Clamp,
+            exposure: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToneMappingUniform {
+    exposure: f32,
+    curve: u32,
+    encode_srgb: u32,
+    _pad: u32,
 }
 
 impl SampleTexturePipeline {
@@ -11,7 +50,16 @@ impl SampleTexturePipeline {
         surface_config: &wgpu::SurfaceConfiguration,
         device: &wgpu::Device,
         sample_view: wgpu::TextureView,
+        tone_mapping: ToneMapping,
     ) -> Self {
+        // sRGB surfaces encode on write, others get the transfer function in the shader
+        let encode_srgb = !surface_config.format.is_srgb();
+        let tone_mapping_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("tone mapping buffer"),
+            contents: bytemuck::cast_slice(&[SampleTexturePipeline::tone_mapping_uniform(tone_mapping, encode_srgb)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Shader"),
             source: wgpu::ShaderSource::Wgsl(include_str!("shaders/sampletexture.wgsl").into()),
@@ -45,6 +93,16 @@ impl SampleTexturePipeline {
                     },
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -60,6 +118,10 @@ impl SampleTexturePipeline {
                     binding: 1,
                     resource: wgpu::BindingResource::TextureView(&sample_view),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: tone_mapping_buffer.as_entire_binding(),
+                },
             ],
         });
 
@@ -113,6 +175,28 @@ impl SampleTexturePipeline {
         SampleTexturePipeline {
             pipeline,
             bind_group,
+            tone_mapping,
+            tone_mapping_buffer,
+            encode_srgb,
+        }
+    }
+
+    pub fn tone_mapping(&self) -> ToneMapping {
+        self.tone_mapping
+    }
+
+    pub fn set_tone_mapping(&mut self, queue: &wgpu::Queue, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping;
+        let uniform = SampleTexturePipeline::tone_mapping_uniform(tone_mapping, self.encode_srgb);
+        queue.write_buffer(&self.tone_mapping_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    fn tone_mapping_uniform(tone_mapping: ToneMapping, encode_srgb: bool) -> ToneMappingUniform {
+        ToneMappingUniform {
+            exposure: tone_mapping.exposure.exp2(),
+            curve: tone_mapping.operator as u32,
+            encode_srgb: encode_srgb as u32,
+            _pad: 0,
         }
     }
 
@@ -129,7 +213,7 @@ impl SampleTexturePipeline {
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &output_view,
+                view: output_view,
                 resolve_target: None,
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Load,
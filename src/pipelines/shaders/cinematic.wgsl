@@ -0,0 +1,299 @@
+// Volumetric path tracing through the transfer-function-mapped volume, appended to medical.wgsl
+
+struct Cinematic {
+    light_direction: vec3<f32>, // towards the light, patient space
+    frame_index: u32,
+    light_color: vec3<f32>,
+    max_bounces: u32,
+    sky_color: vec3<f32>,
+    density_scale: f32,         // extinction per mm at full opacity
+    ground_color: vec3<f32>,
+    anisotropy: f32             // Henyey-Greenstein g
+}
+
+// Where the camera ray first becomes mostly opaque, guides the denoiser
+struct GBuffer {
+    albedo: vec3<f32>,
+    depth: f32,         // mm along the camera ray, 0 where it escapes
+    normal: vec3<f32>,
+    _pad: f32
+}
+
+@group(0) @binding(12)
+var<storage, read_write> accumulation: array<vec4<f32>>;
+
+@group(0) @binding(13)
+var<uniform> cinematic: Cinematic;
+
+@group(0) @binding(18)
+var<storage, read_write> gbuffer: array<GBuffer>;
+
+const PI: f32 = 3.14159265358;
+const maxTrackingSteps: u32 = 1024u;
+const surfaceOpacity: f32 = 0.5; // the G-buffer looks no further into the volume
+
+@compute @workgroup_size(8, 8, 1)
+fn cinematic_main(@builtin(global_invocation_id) GlobalInvocationID: vec3<u32>) {
+    let screen_size: vec2<u32> = textureDimensions(color_buffer);
+    let screen_pos_u32: vec2<u32> = GlobalInvocationID.xy;
+
+    if screen_pos_u32.x >= screen_size.x || screen_pos_u32.y >= screen_size.y {
+        return;
+    }
+
+    let index = screen_pos_u32.y * screen_size.x + screen_pos_u32.x;
+    var rng = index * 9781u + cinematic.frame_index * 6271u + 1u;
+    rand_f(&rng);
+
+    let screen_pos = vec2<f32>(screen_pos_u32) + rand_vec2f(&rng);
+    let radiance = trace_volume(raystart(screen_pos), screen_pos_u32, &rng);
+
+    var accumulated = vec4<f32>(0.0);
+    if cinematic.frame_index > 0u {
+        accumulated = accumulation[index];
+    }
+    accumulated += vec4<f32>(radiance, 1.0);
+    accumulation[index] = accumulated;
+
+    textureStore(color_buffer, vec2<i32>(screen_pos_u32), vec4<f32>(accumulated.rgb / accumulated.a, 1.0));
+
+    if cinematic.frame_index == 0u {
+        let center = vec2<f32>(screen_pos_u32) + vec2<f32>(0.5);
+        gbuffer[index] = volume_gbuffer(raystart(center), screen_pos_u32);
+    }
+}
+
+// The sample contributing most while compositing the classified volume front to back, up to
+// surfaceOpacity; the normal is the opacity gradient there. Meshes count as surfaces facing
+// the camera.
+fn volume_gbuffer(ray: Ray, pixel: vec2<u32>) -> GBuffer {
+    var span = patient_span(ray);
+    let hit = mesh_hit(pixel);
+    var mesh_distance = 0.0;
+    if hit.w > 0.0 {
+        mesh_distance = dot(hit.xyz - ray.start, ray.direction);
+        span.y = min(span.y, mesh_distance);
+    }
+
+    let spacing = volume_spacing();
+    let voxel_size = min(spacing.x, min(spacing.y, spacing.z));
+    let step = max(details.step_size, 0.05);
+    if span.x < span.y {
+        let num_steps = min(u32((span.y - span.x) / (step * voxel_size)) + 1u, maxSteps);
+        var opacity = 0.0;
+        var strongest = 0.0;
+        var strongest_t = 0.0;
+        var strongest_albedo = vec3<f32>(0.0);
+        for (var i = 0u; i < num_steps; i++) {
+            let t = span.x + f32(i) * step * voxel_size;
+            let sample = classify_patient(ray.start + ray.direction * t);
+            let contribution = (1.0 - pow(1.0 - clamp(sample.a, 0.0, 1.0), step)) * (1.0 - opacity);
+            opacity += contribution;
+            if contribution > strongest {
+                strongest = contribution;
+                strongest_t = t;
+                strongest_albedo = sample.rgb;
+            }
+            if opacity >= surfaceOpacity {
+                break;
+            }
+        }
+        if strongest > 0.0 {
+            let p = ray.start + ray.direction * strongest_t;
+            let h = voxel_size;
+            let gradient = vec3<f32>(
+                classify_patient(p + vec3<f32>(h, 0.0, 0.0)).a - classify_patient(p - vec3<f32>(h, 0.0, 0.0)).a,
+                classify_patient(p + vec3<f32>(0.0, h, 0.0)).a - classify_patient(p - vec3<f32>(0.0, h, 0.0)).a,
+                classify_patient(p + vec3<f32>(0.0, 0.0, h)).a - classify_patient(p - vec3<f32>(0.0, 0.0, h)).a
+            );
+            var normal = -ray.direction;
+            if dot(gradient, gradient) > 1e-12 {
+                normal = -normalize(gradient);
+            }
+            return GBuffer(strongest_albedo, strongest_t, normal, 0.0);
+        }
+    }
+
+    if hit.w > 0.0 {
+        let albedo = textureLoad(mesh_color, vec2<i32>(pixel), 0).rgb;
+        return GBuffer(albedo, mesh_distance, -ray.direction, 0.0);
+    }
+    return GBuffer(vec3<f32>(0.0), 0.0, vec3<f32>(0.0), 0.0);
+}
+
+fn trace_volume(camera_ray: Ray, pixel: vec2<u32>, rng: ptr<function, u32>) -> vec3<f32> {
+    var ray = camera_ray;
+    var throughput = vec3<f32>(1.0);
+    var radiance = vec3<f32>(0.0);
+
+    // Primary rays stop at rasterized meshes, which are seen with their own shading
+    let hit = mesh_hit(pixel);
+    var max_distance = 1e30;
+    if hit.w > 0.0 {
+        max_distance = dot(hit.xyz - ray.start, ray.direction);
+    }
+
+    var phase_pdf = 0.0; // of the last scattered direction, 0 for the camera ray
+
+    for (var bounce = 0u; bounce <= cinematic.max_bounces; bounce++) {
+        let distance = delta_track(ray, max_distance, rng);
+        if distance < 0.0 {
+            if bounce == 0u && hit.w > 0.0 {
+                radiance += throughput * textureLoad(mesh_color, vec2<i32>(pixel), 0).rgb;
+                break;
+            }
+            // Escaped; the directional light is only reached through next event estimation,
+            // an environment map is reached both ways
+            var weight = 1.0;
+            if phase_pdf > 0.0 && environment.enabled != 0u {
+                weight = power_heuristic(phase_pdf, environment_pdf(to_environment(ray.direction)));
+            }
+            radiance += throughput * background_light(ray.direction) * weight;
+            break;
+        }
+        max_distance = 1e30;
+
+        let p = ray.start + ray.direction * distance;
+        let albedo = classify_patient(p).rgb;
+        throughput *= albedo;
+
+        // Next event estimation towards the directional light
+        let to_light = normalize(cinematic.light_direction);
+        let phase = phase_hg(dot(ray.direction, to_light), cinematic.anisotropy);
+        let visibility = transmittance(Ray(p, to_light), rng);
+        radiance += throughput * cinematic.light_color * phase * visibility;
+
+        // and towards the bright parts of the environment map
+        if environment.enabled != 0u {
+            let light = sample_environment(rand_vec2f(rng));
+            if light.pdf > 0.0 {
+                let wi = from_environment(light.direction);
+                let light_phase = phase_hg(dot(ray.direction, wi), cinematic.anisotropy);
+                let light_visibility = transmittance(Ray(p, wi), rng);
+                radiance += throughput * light.radiance * light_phase * light_visibility
+                    * power_heuristic(light.pdf, light_phase) / light.pdf;
+            }
+        }
+
+        // Russian roulette after a few bounces
+        if bounce > 2u {
+            let survival = clamp(max(throughput.r, max(throughput.g, throughput.b)), 0.05, 1.0);
+            if rand_f(rng) > survival {
+                break;
+            }
+            throughput /= survival;
+        }
+
+        let direction = sample_hg(ray.direction, cinematic.anisotropy, rng);
+        phase_pdf = phase_hg(dot(ray.direction, direction), cinematic.anisotropy);
+        ray = Ray(p, direction);
+    }
+
+    return radiance;
+}
+
+// Woodcock tracking; returns the collision distance, or -1 when the ray leaves the volume
+// or reaches max_distance
+fn delta_track(ray: Ray, max_distance: f32, rng: ptr<function, u32>) -> f32 {
+    var span = patient_span(ray);
+    span.y = min(span.y, max_distance);
+    if span.x >= span.y {
+        return -1.0;
+    }
+
+    let sigma_max = max(cinematic.density_scale, 1e-6);
+    var t = span.x;
+    for (var i = 0u; i < maxTrackingSteps; i++) {
+        t -= log(1.0 - rand_f(rng)) / sigma_max;
+        if t >= span.y {
+            break;
+        }
+        let sigma = classify_patient(ray.start + ray.direction * t).a * cinematic.density_scale;
+        if rand_f(rng) < sigma / sigma_max {
+            return t;
+        }
+    }
+    return -1.0;
+}
+
+// Ratio tracking estimate of the transmittance along a shadow ray
+fn transmittance(ray: Ray, rng: ptr<function, u32>) -> f32 {
+    let span = patient_span(ray);
+    if span.x >= span.y {
+        return 1.0;
+    }
+
+    let sigma_max = max(cinematic.density_scale, 1e-6);
+    var t = span.x;
+    var result = 1.0;
+    for (var i = 0u; i < maxTrackingSteps; i++) {
+        t -= log(1.0 - rand_f(rng)) / sigma_max;
+        if t >= span.y || result < 1e-3 {
+            break;
+        }
+        let sigma = classify_patient(ray.start + ray.direction * t).a * cinematic.density_scale;
+        result *= 1.0 - sigma / sigma_max;
+    }
+    return result;
+}
+
+fn classify_patient(p: vec3<f32>) -> vec4<f32> {
+    return classify_voxel((volume.patient_to_voxel * vec4<f32>(p, 1.0)).xyz);
+}
+
+// Entry and exit distance of a patient space ray through the volume box, in mm
+fn patient_span(ray: Ray) -> vec2<f32> {
+    let start = (volume.patient_to_voxel * vec4<f32>(ray.start, 1.0)).xyz;
+    let direction = (volume.patient_to_voxel * vec4<f32>(ray.direction, 0.0)).xyz;
+    return intersect_box(Ray(start, direction), vec3<f32>(-0.5), volume.dims.xyz - vec3<f32>(0.5));
+}
+
+// The environment map when one is loaded, otherwise a sky to ground gradient along the
+// patient's superior axis
+fn background_light(direction: vec3<f32>) -> vec3<f32> {
+    if environment.enabled != 0u {
+        return environment_radiance(to_environment(direction));
+    }
+    let t = 0.5 * (direction.z + 1.0);
+    return mix(cinematic.ground_color, cinematic.sky_color, t);
+}
+
+// Patient space is z-up (superior), the environment map y-up
+fn to_environment(direction: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(direction.x, direction.z, -direction.y);
+}
+
+fn from_environment(direction: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(direction.x, -direction.z, direction.y);
+}
+
+fn power_heuristic(pdf: f32, other: f32) -> f32 {
+    let a = pdf * pdf;
+    return a / (a + other * other);
+}
+
+fn phase_hg(cos_theta: f32, g: f32) -> f32 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    return (1.0 - g * g) / (4.0 * PI * denom * sqrt(max(denom, 1e-6)));
+}
+
+fn sample_hg(direction: vec3<f32>, g: f32, rng: ptr<function, u32>) -> vec3<f32> {
+    let u = rand_vec2f(rng);
+    var cos_theta = 1.0 - 2.0 * u.x;
+    if abs(g) > 1e-3 {
+        let sq = (1.0 - g * g) / (1.0 - g + 2.0 * g * u.x);
+        cos_theta = (1.0 + g * g - sq * sq) / (2.0 * g);
+    }
+    let sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+    let phi = 2.0 * PI * u.y;
+
+    var helper = vec3<f32>(1.0, 0.0, 0.0);
+    if abs(direction.x) > 0.9 {
+        helper = vec3<f32>(0.0, 1.0, 0.0);
+    }
+    let tangent = normalize(cross(direction, helper));
+    let bitangent = cross(direction, tangent);
+    return normalize(
+        tangent * (sin_theta * cos(phi)) + bitangent * (sin_theta * sin(phi)) + direction * cos_theta
+    );
+}
@@ -0,0 +1,81 @@
+// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010) over the accumulated path
+// tracer output, guided by the G-buffer of the primary hits.
+
+struct GBuffer {
+    albedo: vec3<f32>,
+    depth: f32,         // distance along the camera ray, 0 where nothing was hit
+    normal: vec3<f32>,
+    _pad: f32
+}
+
+struct Denoise {
+    step: u32,          // pixel distance between taps, 0 copies the input
+    frames: f32,        // accumulated frames, the colour weight tightens as noise goes down
+    sigma_color: f32,
+    sigma_normal: f32,
+    sigma_depth: f32,
+    sigma_albedo: f32
+}
+
+@group(0) @binding(0)
+var input: texture_2d<f32>;
+
+@group(0) @binding(1)
+var output: texture_storage_2d<rgba16float, write>;
+
+@group(0) @binding(2)
+var<storage> gbuffer: array<GBuffer>;
+
+@group(0) @binding(3)
+var<uniform> denoise: Denoise;
+
+const kernel = array<f32, 5>(0.0625, 0.25, 0.375, 0.25, 0.0625); // B3 spline
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) GlobalInvocationID: vec3<u32>) {
+    let size = textureDimensions(output);
+    let pixel = GlobalInvocationID.xy;
+    if pixel.x >= size.x || pixel.y >= size.y {
+        return;
+    }
+
+    let center = textureLoad(input, vec2<i32>(pixel), 0);
+    let g = gbuffer[pixel.y * size.x + pixel.x];
+    // The background is noise free, and filtering it would only blur the environment
+    if denoise.step == 0u || g.depth <= 0.0 {
+        textureStore(output, vec2<i32>(pixel), center);
+        return;
+    }
+
+    let luminance = dot(center.rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+    let color_scale = denoise.sigma_color / sqrt(max(denoise.frames, 1.0));
+    let depth_scale = denoise.sigma_depth * g.depth * f32(denoise.step);
+
+    var sum = vec4<f32>(0.0);
+    var weight_sum = 0.0;
+    for (var y = -2; y <= 2; y++) {
+        for (var x = -2; x <= 2; x++) {
+            let q = vec2<i32>(pixel) + vec2<i32>(x, y) * i32(denoise.step);
+            if any(q < vec2<i32>(0)) || any(q >= vec2<i32>(size)) {
+                continue;
+            }
+            let tap = textureLoad(input, q, 0);
+            let gq = gbuffer[u32(q.y) * size.x + u32(q.x)];
+            if gq.depth <= 0.0 {
+                continue;
+            }
+
+            let tap_luminance = dot(tap.rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+            let w_color = exp(-abs(luminance - tap_luminance) / (color_scale * (luminance + tap_luminance) + 1e-4));
+            let w_normal = pow(max(dot(g.normal, gq.normal), 0.0), denoise.sigma_normal);
+            let w_depth = exp(-abs(g.depth - gq.depth) / (depth_scale + 1e-6));
+            let w_albedo = exp(-length(g.albedo - gq.albedo) / max(denoise.sigma_albedo, 1e-4));
+
+            let w = kernel[x + 2] * kernel[y + 2] * w_color * w_normal * w_depth * w_albedo;
+            sum += tap * w;
+            weight_sum += w;
+        }
+    }
+
+    textureStore(output, vec2<i32>(pixel), sum / max(weight_sum, 1e-6));
+}
@@ -0,0 +1,105 @@
+// Equirectangular environment light, appended to the ray tracer and the volume path tracer.
+// Directions are y-up, PI comes from the including shader.
+
+struct Environment {
+    width: u32,
+    height: u32,
+    enabled: u32,
+    intensity: f32,
+    rotation: f32, // turns around the up axis
+    total: f32     // sum of luminance * sin(theta) over all pixels, 0 disables sampling
+}
+
+struct EnvironmentSample {
+    direction: vec3<f32>,
+    radiance: vec3<f32>,
+    pdf: f32 // solid angle density, 0 when the map can't be sampled
+}
+
+@group(1) @binding(0)
+var environment_map: texture_2d<f32>;
+
+// Marginal CDF over the rows, followed by one conditional CDF per row
+@group(1) @binding(1)
+var<storage> environment_cdf: array<f32>;
+
+@group(1) @binding(2)
+var<uniform> environment: Environment;
+
+fn environment_radiance(direction: vec3<f32>) -> vec3<f32> {
+    let texel = environment_texel(environment_uv(direction));
+    return textureLoad(environment_map, vec2<i32>(texel), 0).rgb * environment.intensity;
+}
+
+// Density with which sample_environment picks the given direction
+fn environment_pdf(direction: vec3<f32>) -> f32 {
+    let uv = environment_uv(direction);
+    let sin_theta = sin(uv.y * PI);
+    if environment.total <= 0.0 || sin_theta <= 1e-4 {
+        return 0.0;
+    }
+    let texel = environment_texel(uv);
+    let size = vec2<f32>(f32(environment.width), f32(environment.height));
+    let row_sin = sin((f32(texel.y) + 0.5) / size.y * PI);
+    let weight = environment_luminance(textureLoad(environment_map, vec2<i32>(texel), 0).rgb) * row_sin;
+    return weight * size.x * size.y / (environment.total * 2.0 * PI * PI * sin_theta);
+}
+
+// Picks a direction proportionally to the map's luminance
+fn sample_environment(xi: vec2<f32>) -> EnvironmentSample {
+    let width = environment.width;
+    let height = environment.height;
+
+    let y = find_interval(0u, height, xi.y);
+    let x = find_interval(height + y * width, width, xi.x);
+    let v = (f32(y) + interval_offset(0u, y, xi.y)) / f32(height);
+    let u = (f32(x) + interval_offset(height + y * width, x, xi.x)) / f32(width);
+
+    let phi = (u - 0.5 - environment.rotation) * 2.0 * PI;
+    let theta = v * PI;
+    let direction = vec3<f32>(sin(theta) * sin(phi), cos(theta), -sin(theta) * cos(phi));
+    return EnvironmentSample(direction, environment_radiance(direction), environment_pdf(direction));
+}
+
+fn environment_uv(direction: vec3<f32>) -> vec2<f32> {
+    let u = fract(atan2(direction.x, -direction.z) / (2.0 * PI) + 0.5 + environment.rotation);
+    let v = acos(clamp(direction.y, -1.0, 1.0)) / PI;
+    return vec2<f32>(u, v);
+}
+
+fn environment_texel(uv: vec2<f32>) -> vec2<u32> {
+    let size = vec2<u32>(environment.width, environment.height);
+    return min(vec2<u32>(uv * vec2<f32>(size)), size - vec2<u32>(1u));
+}
+
+fn environment_luminance(rgb: vec3<f32>) -> f32 {
+    return dot(rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// First entry of the CDF at offset whose value exceeds xi
+fn find_interval(offset: u32, count: u32, xi: f32) -> u32 {
+    var lo = 0u;
+    var hi = count - 1u;
+    while lo < hi {
+        let mid = (lo + hi) / 2u;
+        if environment_cdf[offset + mid] > xi {
+            hi = mid;
+        } else {
+            lo = mid + 1u;
+        }
+    }
+    return lo;
+}
+
+// Position of xi within the chosen interval, for continuous samples inside a pixel
+fn interval_offset(offset: u32, index: u32, xi: f32) -> f32 {
+    var start = 0.0;
+    if index > 0u {
+        start = environment_cdf[offset + index - 1u];
+    }
+    let end = environment_cdf[offset + index];
+    if end <= start {
+        return 0.5;
+    }
+    return clamp((xi - start) / (end - start), 0.0, 1.0);
+}
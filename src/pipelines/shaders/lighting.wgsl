@@ -0,0 +1,103 @@
+// Precomputed light volume for DVR: shadow transmittance towards the light and local ambient
+// occlusion, both estimated from the classified opacity. Appended to medical.wgsl.
+
+struct Lighting {
+    light_direction: vec3<f32>, // towards the light, patient space
+    shadows: f32,               // 0: off, 1: full strength
+    grid_dims: vec3<u32>,
+    occlusion: f32,             // 0: off, 1: full strength
+    ambient: f32,               // share of ambient vs. directional light
+    ao_radius: f32,             // in voxels
+    _pad: vec2<f32>
+}
+
+@group(0) @binding(14)
+var<storage, read_write> light_volume: array<vec2<f32>>;
+
+@group(0) @binding(15)
+var<uniform> lighting: Lighting;
+
+const maxShadowSteps: u32 = 512u;
+const aoDirections = array<vec3<f32>, 14>(
+    vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(-1.0, 0.0, 0.0),
+    vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(0.0, -1.0, 0.0),
+    vec3<f32>(0.0, 0.0, 1.0), vec3<f32>(0.0, 0.0, -1.0),
+    vec3<f32>(0.577, 0.577, 0.577), vec3<f32>(-0.577, 0.577, 0.577),
+    vec3<f32>(0.577, -0.577, 0.577), vec3<f32>(-0.577, -0.577, 0.577),
+    vec3<f32>(0.577, 0.577, -0.577), vec3<f32>(-0.577, 0.577, -0.577),
+    vec3<f32>(0.577, -0.577, -0.577), vec3<f32>(-0.577, -0.577, -0.577)
+);
+
+@compute @workgroup_size(4, 4, 4)
+fn light_main(@builtin(global_invocation_id) GlobalInvocationID: vec3<u32>) {
+    let grid = lighting.grid_dims;
+    let cell = GlobalInvocationID;
+    if cell.x >= grid.x || cell.y >= grid.y || cell.z >= grid.z {
+        return;
+    }
+
+    let dims = volume.dims.xyz;
+    let p = (vec3<f32>(cell) + vec3<f32>(0.5)) / vec3<f32>(grid) * dims - vec3<f32>(0.5);
+
+    // Shadow ray in voxel space, one voxel per step
+    let to_light = normalize((volume.patient_to_voxel * vec4<f32>(lighting.light_direction, 0.0)).xyz);
+    let span = intersect_box(Ray(p, to_light), vec3<f32>(-0.5), dims - vec3<f32>(0.5));
+    var shadow = 1.0;
+    let shadow_steps = min(u32(max(span.y, 0.0)), maxShadowSteps);
+    for (var i = 1u; i <= shadow_steps; i++) {
+        shadow *= 1.0 - classify_voxel(p + to_light * f32(i)).a;
+        if shadow < 0.01 {
+            break;
+        }
+    }
+
+    // Ambient occlusion: mean transmittance over a fixed set of short rays
+    let radius = u32(clamp(lighting.ao_radius, 1.0, 16.0));
+    var ambient = 0.0;
+    for (var d = 0u; d < 14u; d++) {
+        var transmittance = 1.0;
+        for (var i = 1u; i <= radius; i++) {
+            let q = p + aoDirections[d] * f32(i);
+            if any(q < vec3<f32>(-0.5)) || any(q > dims - vec3<f32>(0.5)) {
+                break;
+            }
+            transmittance *= 1.0 - classify_voxel(q).a;
+        }
+        ambient += transmittance;
+    }
+
+    let index = cell.x + grid.x * (cell.y + grid.y * cell.z);
+    light_volume[index] = vec2<f32>(shadow, ambient / 14.0);
+}
+
+// Light factor for a DVR sample at a voxel position
+fn light_at(voxel: vec3<f32>) -> f32 {
+    if lighting.shadows <= 0.0 && lighting.occlusion <= 0.0 {
+        return 1.0;
+    }
+
+    // Trilinear interpolation of the light grid
+    let grid = vec3<i32>(lighting.grid_dims);
+    let g = clamp(
+        (voxel + vec3<f32>(0.5)) / volume.dims.xyz * vec3<f32>(grid) - vec3<f32>(0.5),
+        vec3<f32>(0.0),
+        vec3<f32>(grid - vec3<i32>(1))
+    );
+    let g0 = vec3<i32>(floor(g));
+    let g1 = min(g0 + vec3<i32>(1), grid - vec3<i32>(1));
+    let f = g - vec3<f32>(g0);
+
+    let c00 = mix(light_cell(g0.x, g0.y, g0.z, grid), light_cell(g1.x, g0.y, g0.z, grid), f.x);
+    let c10 = mix(light_cell(g0.x, g1.y, g0.z, grid), light_cell(g1.x, g1.y, g0.z, grid), f.x);
+    let c01 = mix(light_cell(g0.x, g0.y, g1.z, grid), light_cell(g1.x, g0.y, g1.z, grid), f.x);
+    let c11 = mix(light_cell(g0.x, g1.y, g1.z, grid), light_cell(g1.x, g1.y, g1.z, grid), f.x);
+    let light = mix(mix(c00, c10, f.y), mix(c01, c11, f.y), f.z);
+
+    let direct = mix(1.0, light.x, lighting.shadows);
+    let ambient = mix(1.0, light.y, lighting.occlusion);
+    return lighting.ambient * ambient + (1.0 - lighting.ambient) * direct;
+}
+
+fn light_cell(x: i32, y: i32, z: i32, grid: vec3<i32>) -> vec2<f32> {
+    return light_volume[x + grid.x * (y + grid.y * z)];
+}
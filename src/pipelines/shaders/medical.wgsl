@@ -3,21 +3,42 @@ struct Ray {
     direction: vec3<f32>,
 }
 
-struct RayResult {
-    color: vec4<f32>
-    // hit: Hit
-}
-
 struct Details {
     screen_width: f32,
     screen_height: f32,
-    input_width: f32,
-    input_height: f32,
-    input_depth: f32
+    mode: u32,            // mip: 0, dvr: 1, mpr: 2
+    step_size: f32,       // in voxels
+    window_center: f32,
+    window_width: f32,
+    tf_min: f32,
+    tf_max: f32,
+    slice_axis: u32,      // sagittal: 0, coronal: 1, axial: 2
+    slice_index: f32,
+    overlay_opacity: f32,
+    num_labels: u32,
+    blend_mode: u32,      // alpha: 0, additive: 1, checkerboard: 2
+    fusion_opacity: f32,
+    checker_size: f32,    // in mm
+    has_fusion: u32,
+    fusion_tf_min: f32,
+    fusion_tf_max: f32,
+    fusion_value_min: f32,  // the fusion texture holds 0..1 over its value range
+    fusion_value_range: f32
+}
+
+struct VolumeUniform {
+    voxel_to_patient: mat4x4<f32>,
+    patient_to_voxel: mat4x4<f32>,
+    dims: vec4<f32>
+}
+
+struct LabelStyle {
+    color: vec4<f32>,
+    visible: u32
 }
 
 @group(0) @binding(0)
-var color_buffer: texture_storage_2d<rgba8unorm, write>;
+var color_buffer: texture_storage_2d<rgba16float, write>;
 
 @group(0) @binding(1)
 var input: texture_3d<f32>;
@@ -31,13 +52,39 @@ var<uniform> details: Details;
 @group(0) @binding(4)
 var input_sampler: sampler;
 
-var<workgroup> counter: atomic<u32>;
+@group(0) @binding(5)
+var labels: texture_3d<u32>;
+
+@group(0) @binding(6)
+var<storage> label_styles: array<LabelStyle>;
+
+@group(0) @binding(7)
+var<storage> transfer_function: array<vec4<f32>>;
+
+@group(0) @binding(8)
+var<uniform> volume: VolumeUniform;
+
+@group(0) @binding(9)
+var fusion_input: texture_3d<f32>;
+
+@group(0) @binding(10)
+var<uniform> fusion_volume: VolumeUniform;
 
-const numSamples: u32 = 10;
-const useAA: bool = true;
-const numBounceSamples: u32 = 10;
-const numBounces: u32 = 3;
-const scattering: f32 = 0.0;
+@group(0) @binding(11)
+var<storage> fusion_transfer_function: array<vec4<f32>>;
+
+@group(0) @binding(16)
+var mesh_depth: texture_depth_multisampled_2d;
+
+@group(0) @binding(17)
+var mesh_color: texture_2d<f32>;
+
+@group(0) @binding(19)
+var fusion_sampler: sampler; // linear, the fusion volume is usually much coarser
+
+const maxSteps: u32 = 2048u;
+const tfResolution: u32 = 256u;
+const noValue: f32 = -1.0e20;
 
 ////
 
@@ -45,61 +92,287 @@ const scattering: f32 = 0.0;
 fn main(@builtin(global_invocation_id) GlobalInvocationID: vec3<u32>) {
     let screen_size: vec2<u32> = textureDimensions(color_buffer);
     let screen_pos_u32: vec2<u32> = GlobalInvocationID.xy;
-    let global_id_1d: u32 = screen_pos_u32.y * screen_size.x + screen_pos_u32.x;
 
     if screen_pos_u32.x >= screen_size.x || screen_pos_u32.y >= screen_size.y {
         return;
     }
 
-    counter = counter + 1;
-    var rng = screen_pos_u32.x + screen_pos_u32.y * 1000u + counter * 5782582u;
+    let screen_pos = vec2<f32>(screen_pos_u32) + vec2<f32>(0.5);
 
     var color: vec4<f32>;
-    if useAA {
-        let offset = 1.0;
-        for (var i: u32 = 0; i < numSamples; i = i + 1) {
-            let coord = rand_vec2f(&rng) - vec2<f32>(0.5);
-            color += castray(
-                vec2<f32>(f32(screen_pos_u32.x) + coord.x * offset,
-                    f32(screen_pos_u32.y) + coord.y * offset),
-                &rng
-            );
-        }
-        color /= f32(numSamples);
+    if details.mode == 2u {
+        color = render_slice(screen_pos);
     } else {
-        color = castray(vec2<f32>(screen_pos_u32), &rng);
+        color = render_volume(raystart(screen_pos), screen_pos_u32);
     }
 
-    color = linear_to_gamma(color);
     let screen_pos_i32 = vec2<i32>(screen_pos_u32);
     textureStore(color_buffer, screen_pos_i32, color);
 }
 
-fn castray(screen_pos: vec2<f32>, rng: ptr<function, u32>) -> vec4<f32> {
-    var ray = raystart(screen_pos, rng);
-    let hitResult = scene_hit(ray);
-    return hitResult.color;
+fn render_volume(ray: Ray, pixel: vec2<u32>) -> vec4<f32> {
+    // Meshes are opaque: they are the background and end the ray where they are hit
+    let mesh = textureLoad(mesh_color, vec2<i32>(pixel), 0);
+    let background = vec4<f32>(mesh.rgb, 1.0);
+
+    let voxel_ray = to_voxel_ray(ray);
+    var span = intersect_box(voxel_ray, vec3<f32>(-0.5), volume.dims.xyz - vec3<f32>(0.5));
+    let hit = mesh_hit(pixel);
+    if hit.w > 0.0 {
+        let voxel_hit = (volume.patient_to_voxel * vec4<f32>(hit.xyz, 1.0)).xyz;
+        span.y = min(span.y, dot(voxel_hit - voxel_ray.start, voxel_ray.direction));
+    }
+    if span.x >= span.y {
+        return background;
+    }
+
+    let step = max(details.step_size, 0.05);
+    let num_steps = min(u32((span.y - span.x) / step) + 1u, maxSteps);
+
+    if details.mode == 0u {
+        var max_value = noValue;
+        var max_label = 0u;
+        var max_position = voxel_ray.start;
+        var max_fusion = noValue;
+        for (var i = 0u; i < num_steps; i++) {
+            let p = voxel_ray.start + voxel_ray.direction * (span.x + f32(i) * step);
+            let value = sample_volume(p);
+            if value > max_value {
+                max_value = value;
+                max_label = sample_label(p);
+                max_position = p;
+            }
+            max_fusion = max(max_fusion, sample_fusion_value(p));
+        }
+        let gray = apply_window(max_value);
+        var fusion = vec4<f32>(0.0);
+        if max_fusion > noValue {
+            fusion = fusion_transfer(max_fusion);
+        }
+        let color = fuse(vec4<f32>(gray, gray, gray, 1.0), fusion, max_position);
+        let projected = overlay_label(vec4<f32>(color.rgb, 1.0), max_label);
+        return vec4<f32>(max(projected.rgb, background.rgb), 1.0);
+    }
+
+    var accumulated = vec4<f32>(0.0);
+    for (var i = 0u; i < num_steps; i++) {
+        let p = voxel_ray.start + voxel_ray.direction * (span.x + f32(i) * step);
+        let sample = classify_voxel(p);
+
+        // Opacity correction for the step size
+        let alpha = 1.0 - pow(1.0 - clamp(sample.a, 0.0, 1.0), step);
+        if alpha <= 0.0 {
+            continue;
+        }
+        let lit = sample.rgb * light_at(p);
+        accumulated += vec4<f32>(lit * alpha, alpha) * (1.0 - accumulated.a);
+        if accumulated.a > 0.99 {
+            break;
+        }
+    }
+
+    return vec4<f32>(accumulated.rgb + background.rgb * (1.0 - accumulated.a), 1.0);
 }
 
-fn scene_hit(ray: Ray) -> RayResult {
-    var max = -10000.0;
-    for(var i = 0; i < 100; i++)
-    {
-        var pos = ray.start - ray.direction * f32(i) * 0.1;
-        pos.x /= details.input_width;
-        pos.y /= details.input_height;
-        pos.z /= details.input_depth;
-        let sample:vec4<f32> = textureSampleLevel(input, input_sampler, pos, 0.0);
-        if(sample.r > max)
-        {
-            max = sample.r;
-          }
+fn render_slice(screen_pos: vec2<f32>) -> vec4<f32> {
+    let dims = volume.dims.xyz;
+    let spacing = volume_spacing();
+
+    // In-plane axes; the vertical axis runs head-up for coronal and sagittal slices
+    var u_axis = 0u;
+    var v_axis = 1u;
+    if details.slice_axis == 0u {
+        u_axis = 1u;
+        v_axis = 2u;
+    } else if details.slice_axis == 1u {
+        v_axis = 2u;
     }
-    let background = vec4<f32>(max/1000.0, max/1000.0, max/1000.0, 1.0);
-    return RayResult(background);
+
+    let extent = vec2<f32>(dims[u_axis] * spacing[u_axis], dims[v_axis] * spacing[v_axis]);
+    let screen = vec2<f32>(details.screen_width, details.screen_height);
+    let mm_per_pixel = max(extent.x / screen.x, extent.y / screen.y);
+    let mm = (screen_pos - screen * 0.5) * mm_per_pixel + extent * 0.5;
+
+    var p = vec3<f32>(0.0);
+    p[details.slice_axis] = details.slice_index;
+    p[u_axis] = mm.x / spacing[u_axis] - 0.5;
+    p[v_axis] = mm.y / spacing[v_axis] - 0.5;
+    if v_axis == 2u {
+        p[v_axis] = dims[v_axis] - 1.0 - p[v_axis];
+    }
+
+    if any(p < vec3<f32>(-0.5)) || any(p > dims - vec3<f32>(0.5)) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+
+    let gray = apply_window(sample_volume(p));
+    let color = fuse(vec4<f32>(gray, gray, gray, 1.0), sample_fusion(p), p);
+    return overlay_label(vec4<f32>(color.rgb, 1.0), sample_label(p));
+}
+
+//// classification
+
+// Fully classified sample: labels, transfer function and fusion volume
+fn classify_voxel(voxel: vec3<f32>) -> vec4<f32> {
+    let sample = fuse(classify(sample_volume(voxel), sample_label(voxel)), sample_fusion(voxel), voxel);
+    return clamp(sample, vec4<f32>(0.0), vec4<f32>(1.0));
+}
+
+// Visible labels take their own colour and opacity, everything else goes through the transfer function
+fn classify(value: f32, label: u32) -> vec4<f32> {
+    if label != 0u && label < details.num_labels {
+        let style = label_styles[label];
+        if style.visible != 0u {
+            return style.color;
+        }
+    }
+    return transfer(value);
+}
+
+fn transfer(value: f32) -> vec4<f32> {
+    let range = max(details.tf_max - details.tf_min, 1e-6);
+    let t = clamp((value - details.tf_min) / range, 0.0, 1.0) * f32(tfResolution - 1u);
+    let i = u32(floor(t));
+    let j = min(i + 1u, tfResolution - 1u);
+    return mix(transfer_function[i], transfer_function[j], fract(t));
+}
+
+fn fusion_transfer(value: f32) -> vec4<f32> {
+    let range = max(details.fusion_tf_max - details.fusion_tf_min, 1e-6);
+    let t = clamp((value - details.fusion_tf_min) / range, 0.0, 1.0) * f32(tfResolution - 1u);
+    let i = u32(floor(t));
+    let j = min(i + 1u, tfResolution - 1u);
+    return mix(fusion_transfer_function[i], fusion_transfer_function[j], fract(t));
+}
+
+// Combines a classified primary sample with the classified fusion sample at the same position
+fn fuse(primary: vec4<f32>, fusion: vec4<f32>, voxel: vec3<f32>) -> vec4<f32> {
+    if details.has_fusion == 0u {
+        return primary;
+    }
+    let weight = clamp(fusion.a * details.fusion_opacity, 0.0, 1.0);
+    switch details.blend_mode {
+        case 1u: {
+            return vec4<f32>(primary.rgb + fusion.rgb * weight, max(primary.a, weight));
+        }
+        case 2u: {
+            let patient = (volume.voxel_to_patient * vec4<f32>(voxel, 1.0)).xyz;
+            let cell = vec3<i32>(floor(patient / details.checker_size));
+            if ((cell.x + cell.y + cell.z) & 1) == 1 {
+                // Fusion composited over the primary sample, so the anatomy shows through
+                return vec4<f32>(mix(primary.rgb, fusion.rgb, weight), weight + primary.a * (1.0 - weight));
+            }
+            return primary;
+        }
+        default: {
+            return vec4<f32>(mix(primary.rgb, fusion.rgb, weight), max(primary.a, weight));
+        }
+    }
+}
+
+fn apply_window(value: f32) -> f32 {
+    let width = max(details.window_width, 1e-6);
+    return clamp((value - (details.window_center - 0.5 * width)) / width, 0.0, 1.0);
+}
+
+fn overlay_label(color: vec4<f32>, label: u32) -> vec4<f32> {
+    if label == 0u || label >= details.num_labels {
+        return color;
+    }
+    let style = label_styles[label];
+    if style.visible == 0u {
+        return color;
+    }
+    let alpha = style.color.a * details.overlay_opacity;
+    return vec4<f32>(mix(color.rgb, style.color.rgb, alpha), color.a);
+}
+
+//// volume access
+
+fn sample_volume(voxel: vec3<f32>) -> f32 {
+    let uvw = (voxel + vec3<f32>(0.5)) / volume.dims.xyz;
+    return textureSampleLevel(input, input_sampler, uvw, 0.0).r;
+}
+
+// Resamples the fusion volume at a primary voxel position, through patient space
+fn sample_fusion_value(voxel: vec3<f32>) -> f32 {
+    if details.has_fusion == 0u {
+        return noValue;
+    }
+    let patient = volume.voxel_to_patient * vec4<f32>(voxel, 1.0);
+    let fusion_voxel = (fusion_volume.patient_to_voxel * patient).xyz;
+    let dims = fusion_volume.dims.xyz;
+    if any(fusion_voxel < vec3<f32>(-0.5)) || any(fusion_voxel > dims - vec3<f32>(0.5)) {
+        return noValue;
+    }
+    let uvw = (fusion_voxel + vec3<f32>(0.5)) / dims;
+    let normalized = textureSampleLevel(fusion_input, fusion_sampler, uvw, 0.0).r;
+    return details.fusion_value_min + normalized * details.fusion_value_range;
+}
+
+// Classified fusion sample, transparent outside the fusion volume
+fn sample_fusion(voxel: vec3<f32>) -> vec4<f32> {
+    let value = sample_fusion_value(voxel);
+    if value <= noValue {
+        return vec4<f32>(0.0);
+    }
+    return fusion_transfer(value);
+}
+
+fn sample_label(voxel: vec3<f32>) -> u32 {
+    if details.num_labels == 0u {
+        return 0u;
+    }
+    let max_index = vec3<i32>(textureDimensions(labels)) - vec3<i32>(1);
+    let index = clamp(vec3<i32>(round(voxel)), vec3<i32>(0), max_index);
+    return textureLoad(labels, index, 0).r;
+}
+
+// Nearest rasterized mesh surface under the pixel in patient space, w is 0 where there is none
+fn mesh_hit(pixel: vec2<u32>) -> vec4<f32> {
+    var depth = 1.0;
+    let samples = i32(textureNumSamples(mesh_depth));
+    for (var i = 0; i < samples; i++) {
+        depth = min(depth, textureLoad(mesh_depth, vec2<i32>(pixel), i));
+    }
+    if depth >= 1.0 {
+        return vec4<f32>(0.0);
+    }
+
+    let uv = (vec2<f32>(pixel) + vec2<f32>(0.5)) / vec2<f32>(details.screen_width, details.screen_height);
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let p = camera.inv_view_proj * ndc;
+    return vec4<f32>(p.xyz / p.w, 1.0);
+}
+
+fn volume_spacing() -> vec3<f32> {
+    return vec3<f32>(
+        length(volume.voxel_to_patient[0].xyz),
+        length(volume.voxel_to_patient[1].xyz),
+        length(volume.voxel_to_patient[2].xyz)
+    );
+}
+
+fn to_voxel_ray(ray: Ray) -> Ray {
+    let start = (volume.patient_to_voxel * vec4<f32>(ray.start, 1.0)).xyz;
+    let direction = (volume.patient_to_voxel * vec4<f32>(ray.direction, 0.0)).xyz;
+    return Ray(start, normalize(direction));
+}
+
+// Returns the entry and exit distance along the ray, entry >= exit on a miss
+fn intersect_box(ray: Ray, box_min: vec3<f32>, box_max: vec3<f32>) -> vec2<f32> {
+    let safe_direction = select(ray.direction, vec3<f32>(1e-8), abs(ray.direction) < vec3<f32>(1e-8));
+    let inv_direction = 1.0 / safe_direction;
+    let t0 = (box_min - ray.start) * inv_direction;
+    let t1 = (box_max - ray.start) * inv_direction;
+    let t_min = min(t0, t1);
+    let t_max = max(t0, t1);
+    let near = max(max(t_min.x, t_min.y), max(t_min.z, 0.0));
+    let far = min(min(t_max.x, t_max.y), t_max.z);
+    return vec2<f32>(near, far);
 }
 
-fn raystart(screenPos: vec2<f32>, rng: ptr<function, u32>) -> Ray {
+fn raystart(screenPos: vec2<f32>) -> Ray {
     let s = screenPos.x / details.screen_width;
     let t = screenPos.y / details.screen_height;
 
@@ -109,10 +382,8 @@ fn raystart(screenPos: vec2<f32>, rng: ptr<function, u32>) -> Ray {
         return Ray(origin, direction);
     } else {
         let ray_target = camera.lower_left_corner + s * camera.horizontal + t * camera.vertical;
-        let random_disk = vec2<f32>(camera.lens_radius) * normalize(rand_vec2f(rng));
-        let lens_offset = camera.u_axis * random_disk.x + camera.v_axis * random_disk.y;
-        let origin = camera.eye + lens_offset;
-        let direction = normalize(ray_target - camera.eye - lens_offset);
+        let origin = camera.eye;
+        let direction = normalize(ray_target - camera.eye);
         return Ray(origin, direction);
     }
 }
@@ -1,9 +1,22 @@
 // Vertex shader
 
 struct CameraUniform {
+    eye: vec3<f32>,
+    lens_radius: f32,
+    u_axis: vec3<f32>,
+    z_near: f32,
+    v_axis: vec3<f32>,
+    z_far: f32,
+    w_axis: vec3<f32>,
+    projection: u32,
+    horizontal: vec3<f32>,
+    _pad2: f32,
+    vertical: vec3<f32>,
+    _pad3: f32,
+    lower_left_corner: vec3<f32>,
+    _pad4: f32,
     view_proj: mat4x4<f32>,
-    position: vec3<f32>,
-    direction: vec3<f32>
+    inv_view_proj: mat4x4<f32>
 };
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
@@ -15,6 +28,7 @@ struct VertexInput {
 
 struct VertexOutput {
     @location(0) normal: vec3<f32>,
+    @location(1) position: vec3<f32>,
     @builtin(position) clip_position: vec4<f32>,
 };
 
@@ -23,15 +37,48 @@ fn vs_main(model: VertexInput) -> VertexOutput {
     var out: VertexOutput;
     out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
     out.normal = model.normal;
+    out.position = model.position;
     return out;
 }
 
 // Fragment shader
 
+struct Material {
+    base_color: vec4<f32>,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32
+};
+@group(1) @binding(0)
+var<uniform> material: Material;
+
+// Linear colour, the tonemap pass encodes the final image to sRGB once
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    // let pp = length(in.normal.z);
-    let light = vec3(0.0, 1.0, 0.0);
-    let pp = dot(in.normal, light); 
-    return vec4<f32>(pp, pp, pp, 1.0);
+    // Headlight, two sided
+    var to_eye = camera.w_axis;
+    if camera.projection == 1u {
+        to_eye = camera.eye - in.position;
+    }
+    let v = normalize(to_eye);
+    var n = normalize(in.normal);
+    if dot(n, v) < 0.0 {
+        n = -n;
+    }
+    let n_dot_v = max(dot(n, v), 0.0);
+
+    // Metallic-roughness with the light at the eye: half vector equals the view direction
+    let albedo = material.base_color.rgb;
+    let diffuse = albedo * (1.0 - material.metallic);
+    let f0 = mix(vec3<f32>(0.04), albedo, material.metallic);
+    let alpha = max(material.roughness * material.roughness, 0.02);
+    let alpha2 = alpha * alpha;
+    let d = n_dot_v * n_dot_v * (alpha2 - 1.0) + 1.0;
+    let ndf = alpha2 / (3.14159265 * d * d);
+    // D F G / (4 n.l n.v) * n.l, with G ~ 1
+    let specular = f0 * min(ndf * 0.25, 16.0);
+
+    let ambient = 0.15 * albedo;
+    let color = ambient + diffuse * n_dot_v + specular + material.emissive;
+    return vec4<f32>(color, 1.0);
 }
@@ -12,7 +12,9 @@ struct CameraUniform {
     vertical: vec3<f32>,
     _pad3: f32,
     lower_left_corner: vec3<f32>,
-    _pad4: f32
+    _pad4: f32,
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>
 };
 struct Ray {
     start: vec3<f32>,
@@ -27,20 +29,19 @@ struct Hit {
     center: vec3<f32>
 }
 
-struct ScatterRecord {
-    scatteredRay: Ray,
-    attenuation: f32
-}
-
 struct Material {
     albedo: vec4<f32>,
     emission: vec4<f32>,
-    roughness: f32
+    roughness: f32,
+    metallic: f32,
+    transmission: f32,
+    ior: f32
 }
 
 struct RayResult {
     material: Material,
-    hit: Hit
+    hit: Hit,
+    sphere: u32 // index of the hit sphere, noSphere for triangles and misses
 }
 
 struct Sphere {
@@ -51,11 +52,47 @@ struct Sphere {
 
 struct Details {
     screen_width: f32,
-    screen_height: f32
+    screen_height: f32,
+    sphere_count: u32,
+    first_light: u32, // spheres from here on are emissive
+    max_bounces: u32,
+    samples_per_pixel: u32,
+    antialias: u32,
+    frame_index: u32 // frames accumulated since the last reset
+}
+
+struct BvhNode {
+    min: vec3<f32>,
+    left_or_first: u32, // leaf: first triangle, interior: left child (right child follows)
+    max: vec3<f32>,
+    count: u32          // triangles in a leaf, 0 for interior nodes
+}
+
+struct Triangle {
+    v0: vec3<f32>,
+    material: u32,
+    v1: vec3<f32>,
+    v2: vec3<f32>,
+    n0: vec3<f32>,
+    n1: vec3<f32>,
+    n2: vec3<f32>
+}
+
+// Primary hit at the pixel centre, guides the denoiser
+struct GBuffer {
+    albedo: vec3<f32>,
+    depth: f32,         // 0 where the camera ray escapes
+    normal: vec3<f32>,
+    _pad: f32
+}
+
+struct TriangleHit {
+    t: f32,
+    index: u32
 }
 
 @group(0) @binding(0)
-var color_buffer: texture_storage_2d<rgba8unorm, write>;
+var color_buffer: texture_storage_2d<rgba16float, write>;
 
 @group(0) @binding(1)
 var<uniform> camera: CameraUniform;
@@ -66,14 +103,31 @@ var<storage> spheres: array<Sphere>;
 @group(0) @binding(3)
 var<uniform> details: Details;
 
+@group(0) @binding(4)
+var<storage> bvh_nodes: array<BvhNode>;
+
+@group(0) @binding(5)
+var<storage> triangles: array<Triangle>;
+
+@group(0) @binding(6)
+var<storage> mesh_materials: array<Material>;
+
+// Running mean of all frames since the camera or scene last changed, in linear HDR
+@group(0) @binding(7)
+var<storage, read_write> accumulation: array<vec4<f32>>;
+
+@group(0) @binding(8)
+var<storage, read_write> gbuffer: array<GBuffer>;
+
 var<workgroup> counter: atomic<u32>;
 
 const PI: f32 = 3.14159265358;
-const numSamples: u32 = 10u;
-const useAA: bool = true;
-const numBounceSamples: u32 = 10u;
-const numBounces: u32 = 3u;
-const scattering: f32 = 0.0;
+const rouletteStart: u32 = 3u; // bounces before paths may be terminated early
+const bvhStackSize: u32 = 32u;
+const noTriangle: u32 = 0xffffffffu;
+const noSphere: u32 = 0xffffffffu;
+const rayOffset: f32 = 1e-4;
+const minAlpha: f32 = 1e-3; // GGX alpha, smoother surfaces are treated as this sharp
 
 ////
 
@@ -88,51 +142,121 @@ fn main(@builtin(global_invocation_id) GlobalInvocationID: vec3<u32>) {
     }
 
     counter = counter + 1;
-    var rng = screen_pos_u32.x + screen_pos_u32.y * 1000u + counter * 5782582u;
+    var rng = (screen_pos_u32.x + screen_pos_u32.y * 1000u + counter * 5782582u) ^ (details.frame_index * 2654435769u);
+    rand_f(&rng); // decorrelates the frames
+
+    let samples = max(details.samples_per_pixel, 1u);
+    var color = vec4<f32>(0.0);
+    for (var i = 0u; i < samples; i++) {
+        var jitter = vec2<f32>(0.0);
+        if details.antialias != 0u {
+            jitter = rand_vec2f(&rng) - vec2<f32>(0.5);
+        }
+        color += castray(vec2<f32>(screen_pos_u32) + jitter, &rng);
+    }
+    color /= f32(samples);
+
+    if details.frame_index > 0u {
+        color = mix(accumulation[global_id_1d], color, 1.0 / f32(details.frame_index + 1u));
+    }
+    accumulation[global_id_1d] = color;
+
+    // The primary hits don't change while accumulating
+    if details.frame_index == 0u {
+        gbuffer[global_id_1d] = primary_gbuffer(vec2<f32>(screen_pos_u32) + vec2<f32>(0.5));
+    }
 
-    var color = castray(vec2<f32>(screen_pos_u32), &rng);
-//    color = linear_to_gamma(color);
     let screen_pos_i32 = vec2<i32>(screen_pos_u32);
     textureStore(color_buffer, screen_pos_i32, color);
-
-    //
-    // var color: vec4<f32>;
-    // if useAA {
-    //     let offset = 1.0;
-    //     for (var i: u32 = 0; i < numSamples; i = i + 1) {
-    //         let coord = rand_vec2f(&rng) - vec2<f32>(0.5);
-    //         color += castray(
-    //             vec2<f32>(f32(screen_pos_u32.x) + coord.x * offset,
-    //                 f32(screen_pos_u32.y) + coord.y * offset),
-    //             &rng
-    //         );
-    //     }
-    //     color /= f32(numSamples);
-    // } else {
-    //     color = castray(vec2<f32>(screen_pos_u32), &rng);
-    // }
-    //
-    // color = linear_to_gamma(color);
-    // let screen_pos_i32 = vec2<i32>(screen_pos_u32);
-    // textureStore(color_buffer, screen_pos_i32, color);
 }
 
 fn castray(screen_pos: vec2<f32>, rng: ptr<function, u32>) -> vec4<f32> {
     var ray = create_ray(screen_pos);
-    var color = vec4<f32>(1.0);
-    let rayResult = scene_hit(ray);
-    if rayResult.hit.hit {
-        let costheta = max(0.0, dot(rayResult.hit.normal, -ray.direction));
-        color *= brdf_lambertian(-ray.direction, rayResult.hit.normal, rayResult.material) * costheta;
-
-        let scat = scatter(rayResult, rng);
-        let rayResult2 = scene_hit(scat.scatteredRay);
-        if rayResult2.hit.hit {
-            let costheta2 = max(0.0, dot(rayResult2.hit.normal, scat.scatteredRay.direction));
-            color *= brdf_lambertian(scat.scatteredRay.direction, rayResult2.hit.normal, rayResult2.material) * costheta2;
+    var radiance = vec3<f32>(0.0);
+    var throughput = vec3<f32>(1.0);
+    var bsdf_pdf = 0.0; // of the last sampled direction, 0 for camera rays and specular bounces
+    var previous = ray.start;
+
+    for (var bounce = 0u; bounce <= details.max_bounces; bounce++) {
+        let result = scene_hit(ray);
+        if !result.hit.hit {
+            // An importance sampled environment shares its contribution like the lights do
+            var background_weight = 1.0;
+            if bsdf_pdf > 0.0 && environment.enabled != 0u {
+                background_weight = power_heuristic(bsdf_pdf, environment_pdf(ray.direction));
+            }
+            radiance += throughput * background(ray.direction) * background_weight;
+            break;
+        }
+        let material = result.material;
+
+        // Lights found by BSDF sampling share their contribution with the light sampling below
+        var emission_weight = 1.0;
+        if bsdf_pdf > 0.0 && result.sphere != noSphere && result.sphere >= details.first_light {
+            emission_weight = power_heuristic(bsdf_pdf, light_pdf(previous, result.sphere));
+        }
+        radiance += throughput * material.emission.rgb * emission_weight;
+        if bounce == details.max_bounces {
+            break;
+        }
+
+        // Russian roulette, dim paths end early and the survivors carry their energy
+        if bounce >= rouletteStart {
+            let survival = min(max(throughput.r, max(throughput.g, throughput.b)), 0.95);
+            if rand_f(rng) >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+
+        let front = dot(ray.direction, result.hit.normal) < 0.0;
+        let n = select(-result.hit.normal, result.hit.normal, front);
+        let wo = -ray.direction;
+        let p = result.hit.p;
+
+        if rand_f(rng) < material.transmission {
+            let wi = sample_dielectric(material, n, wo, front, rng);
+            let refracted = dot(wi, n) < 0.0;
+            if refracted {
+                throughput *= material.albedo.rgb;
+            }
+            ray = Ray(p + select(n, -n, refracted) * rayOffset, wi);
+            bsdf_pdf = 0.0;
+        } else {
+            radiance += throughput * sample_light(p, n, wo, material, rng);
+            radiance += throughput * sample_environment_light(p, n, wo, material, rng);
+
+            let wi = sample_opaque(material, n, wo, rng);
+            let bsdf = eval_bsdf(material, n, wo, wi);
+            if bsdf.a <= 0.0 {
+                break;
+            }
+            throughput *= bsdf.rgb / bsdf.a;
+            ray = Ray(p + n * rayOffset, wi);
+            bsdf_pdf = bsdf.a;
         }
+        previous = p;
     }
-    return color;
+    return vec4<f32>(radiance, 1.0);
+}
+
+fn primary_gbuffer(screen_pos: vec2<f32>) -> GBuffer {
+    let ray = create_ray(screen_pos);
+    let result = scene_hit(ray);
+    if !result.hit.hit {
+        return GBuffer(vec3<f32>(0.0), 0.0, vec3<f32>(0.0), 0.0);
+    }
+    let normal = result.hit.normal;
+    let n = select(-normal, normal, dot(ray.direction, normal) < 0.0);
+    return GBuffer(result.material.albedo.rgb, result.hit.t, n, 0.0);
+}
+
+// The environment map when one is loaded, a uniform white sky otherwise
+fn background(direction: vec3<f32>) -> vec3<f32> {
+    if environment.enabled != 0u {
+        return environment_radiance(direction);
+    }
+    return vec3<f32>(1.0);
 }
 
 fn create_ray(screenPos: vec2<f32>) -> Ray {
@@ -152,45 +276,243 @@ fn create_ray(screenPos: vec2<f32>) -> Ray {
 }
 
 fn scene_hit(ray: Ray) -> RayResult {
-    var hit_anything = false;
     var closest = Hit(false, 9999999.0, vec3<f32>(0.0), vec3<f32>(0.0), vec3<f32>(0.0));
     var material: Material;
+    var sphere = noSphere;
 
-    for (var i = 0u; i < arrayLength(&spheres); i++) {
+    for (var i = 0u; i < details.sphere_count; i++) {
         let hit_sphere = hit_sphere(spheres[i].pos, spheres[i].radius, ray, 0.000001, 100000.0);
-        if hit_sphere.hit {
-            hit_anything = true;
-            if hit_sphere.t < closest.t {
-                closest = hit_sphere;
-                material = spheres[i].material;
-            }
+        if hit_sphere.hit && hit_sphere.t < closest.t {
+            closest = hit_sphere;
+            material = spheres[i].material;
+            sphere = i;
         }
     }
 
-    if hit_anything {
-        let color = material;
-        return RayResult(color, closest);
+    let triangle_hit = hit_bvh(ray, closest.t);
+    if triangle_hit.index != noTriangle {
+        let triangle = triangles[triangle_hit.index];
+        let p = ray.start + ray.direction * triangle_hit.t;
+        let bary = computeBarycentricCoords(p, triangle.v0, triangle.v1, triangle.v2);
+        var normal = triangle.n0 * bary.x + triangle.n1 * bary.y + triangle.n2 * bary.z;
+        if dot(normal, normal) < 1e-12 {
+            normal = cross(triangle.v1 - triangle.v0, triangle.v2 - triangle.v0);
+        }
+        closest = Hit(true, triangle_hit.t, p, normalize(normal), vec3<f32>(0.0));
+        material = mesh_materials[triangle.material];
+        sphere = noSphere;
     }
 
-    return RayResult(material, closest);
+    return RayResult(material, closest, sphere);
+}
+
+// Closest triangle nearer than t_max, nearer child first so far subtrees get culled
+fn hit_bvh(ray: Ray, t_max: f32) -> TriangleHit {
+    var result = TriangleHit(t_max, noTriangle);
+    let inv_direction = 1.0 / select(ray.direction, vec3<f32>(1e-8), abs(ray.direction) < vec3<f32>(1e-8));
+
+    var stack: array<u32, bvhStackSize>;
+    var stack_size = 1u;
+    stack[0] = 0u;
+    while stack_size > 0u {
+        stack_size -= 1u;
+        let node = bvh_nodes[stack[stack_size]];
+        if hit_aabb(ray.start, inv_direction, node.min, node.max) >= result.t {
+            continue;
+        }
+
+        if node.count > 0u {
+            for (var i = node.left_or_first; i < node.left_or_first + node.count; i++) {
+                let triangle = triangles[i];
+                let t = intersectTriangle(ray, triangle.v0, triangle.v1, triangle.v2);
+                if t > 0.0 && t < result.t {
+                    result = TriangleHit(t, i);
+                }
+            }
+        } else if stack_size + 2u <= bvhStackSize {
+            let left = node.left_or_first;
+            let t_left = hit_aabb(ray.start, inv_direction, bvh_nodes[left].min, bvh_nodes[left].max);
+            let t_right = hit_aabb(ray.start, inv_direction, bvh_nodes[left + 1u].min, bvh_nodes[left + 1u].max);
+            if t_left < t_right {
+                stack[stack_size] = left + 1u;
+                stack[stack_size + 1u] = left;
+            } else {
+                stack[stack_size] = left;
+                stack[stack_size + 1u] = left + 1u;
+            }
+            stack_size += 2u;
+        }
+    }
+    return result;
 }
 
-fn brdf_lambertian(w_in: vec3<f32>, normal: vec3<f32>, material: Material) -> vec4<f32> {
-    let cosin = dot(normal, w_in);
-    if cosin <= 0.0 {
+//// materials
+
+// Diffuse plus GGX specular, metals tint the reflection and lose the diffuse part.
+// rgb: bsdf * cos(theta_i), a: pdf of sample_opaque, both scaled by the opaque share.
+fn eval_bsdf(material: Material, n: vec3<f32>, wo: vec3<f32>, wi: vec3<f32>) -> vec4<f32> {
+    let cos_i = dot(n, wi);
+    let cos_o = dot(n, wo);
+    if cos_i <= 0.0 || cos_o <= 0.0 {
         return vec4<f32>(0.0);
     }
-    return material.albedo / PI;
+    let albedo = material.albedo.rgb;
+    let alpha = ggx_alpha(material.roughness);
+    let h = normalize(wo + wi);
+    let cos_h = max(dot(n, h), 0.0);
+    let o_h = max(dot(wo, h), 1e-6);
+
+    let fresnel = fresnel_schlick(mix(vec3<f32>(0.04), albedo, material.metallic), o_h);
+    let d = ggx_d(cos_h, alpha);
+    let g = smith_g1(cos_i, alpha) * smith_g1(cos_o, alpha);
+    let specular = fresnel * d * g / (4.0 * cos_o);
+    let diffuse = (1.0 - fresnel) * (1.0 - material.metallic) * albedo / PI * cos_i;
+
+    let p_specular = specular_probability(material);
+    let pdf = p_specular * d * cos_h / (4.0 * o_h) + (1.0 - p_specular) * cos_i / PI;
+    let opaque = 1.0 - material.transmission;
+    return vec4<f32>(specular + diffuse, pdf) * opaque;
+}
+
+fn sample_opaque(material: Material, n: vec3<f32>, wo: vec3<f32>, rng: ptr<function, u32>) -> vec3<f32> {
+    let frame = tangent_frame(n);
+    let xi = rand_vec2f(rng);
+    let phi = 2.0 * PI * xi.y;
+    if rand_f(rng) < specular_probability(material) {
+        // GGX distribution of normals
+        let alpha = ggx_alpha(material.roughness);
+        let cos2 = (1.0 - xi.x) / (1.0 + (alpha * alpha - 1.0) * xi.x);
+        let sin_t = sqrt(max(0.0, 1.0 - cos2));
+        let h = frame * vec3<f32>(cos(phi) * sin_t, sin(phi) * sin_t, sqrt(cos2));
+        return reflect(-wo, h);
+    }
+    // Cosine weighted hemisphere
+    let r = sqrt(xi.x);
+    return frame * vec3<f32>(cos(phi) * r, sin(phi) * r, sqrt(max(0.0, 1.0 - xi.x)));
+}
+
+// Smooth glass, reflects or refracts by the Fresnel term. `n` faces `wo`.
+fn sample_dielectric(material: Material, n: vec3<f32>, wo: vec3<f32>, front: bool, rng: ptr<function, u32>) -> vec3<f32> {
+    let eta = select(material.ior, 1.0 / material.ior, front);
+    let cos_o = dot(n, wo);
+    let sin2_t = eta * eta * (1.0 - cos_o * cos_o);
+    var reflectance = 1.0; // total internal reflection
+    if sin2_t < 1.0 {
+        // Schlick on the angle in the optically thinner medium
+        let cos_thin = select(cos_o, sqrt(1.0 - sin2_t), eta > 1.0);
+        let r0 = pow((1.0 - material.ior) / (1.0 + material.ior), 2.0);
+        reflectance = r0 + (1.0 - r0) * pow(1.0 - cos_thin, 5.0);
+    }
+    if rand_f(rng) < reflectance {
+        return reflect(-wo, n);
+    }
+    return refract(-wo, n, eta);
+}
+
+fn specular_probability(material: Material) -> f32 {
+    return mix(0.5, 1.0, material.metallic);
+}
+
+fn ggx_alpha(roughness: f32) -> f32 {
+    return max(roughness * roughness, minAlpha);
+}
+
+fn ggx_d(cos_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let denom = cos_h * cos_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denom * denom);
+}
+
+fn smith_g1(cos_theta: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    return 2.0 * cos_theta / (cos_theta + sqrt(a2 + (1.0 - a2) * cos_theta * cos_theta));
+}
+
+fn fresnel_schlick(f0: vec3<f32>, cos_theta: f32) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// Orthonormal basis with n as z (Duff et al. 2017)
+fn tangent_frame(n: vec3<f32>) -> mat3x3<f32> {
+    let s = select(-1.0, 1.0, n.z >= 0.0);
+    let a = -1.0 / (s + n.z);
+    let b = n.x * n.y * a;
+    let t = vec3<f32>(1.0 + s * n.x * n.x * a, s * b, -s * n.x);
+    let bitangent = vec3<f32>(b, s + n.y * n.y * a, -n.y);
+    return mat3x3<f32>(t, bitangent, n);
+}
+
+//// lights
+
+// Next event estimation: a uniformly chosen emissive sphere, sampled over the cone it subtends
+fn sample_light(p: vec3<f32>, n: vec3<f32>, wo: vec3<f32>, material: Material, rng: ptr<function, u32>) -> vec3<f32> {
+    let light_count = details.sphere_count - details.first_light;
+    if light_count == 0u {
+        return vec3<f32>(0.0);
+    }
+    let index = details.first_light + min(u32(rand_f(rng) * f32(light_count)), light_count - 1u);
+    let light = spheres[index];
+
+    let to_center = light.pos - p;
+    let dist2 = dot(to_center, to_center);
+    let ratio = light.radius * light.radius / dist2;
+    if ratio >= 1.0 {
+        return vec3<f32>(0.0);
+    }
+    let cos_max = sqrt(1.0 - ratio);
+    let one_minus_cos_max = ratio / (1.0 + cos_max); // stable for small and distant lights
+    let xi = rand_vec2f(rng);
+    let cos_t = 1.0 - xi.x * one_minus_cos_max;
+    let sin_t = sqrt(max(0.0, 1.0 - cos_t * cos_t));
+    let phi = 2.0 * PI * xi.y;
+    let wi = tangent_frame(to_center / sqrt(dist2)) * vec3<f32>(cos(phi) * sin_t, sin(phi) * sin_t, cos_t);
+
+    let bsdf = eval_bsdf(material, n, wo, wi);
+    if all(bsdf.rgb <= vec3<f32>(0.0)) {
+        return vec3<f32>(0.0);
+    }
+    if scene_hit(Ray(p + n * rayOffset, wi)).sphere != index {
+        return vec3<f32>(0.0); // occluded
+    }
+    let pdf = 1.0 / (2.0 * PI * one_minus_cos_max * f32(light_count));
+    return light.material.emission.rgb * bsdf.rgb * power_heuristic(pdf, bsdf.a) / pdf;
+}
+
+// Next event estimation towards the environment map, proportional to its brightness
+fn sample_environment_light(p: vec3<f32>, n: vec3<f32>, wo: vec3<f32>, material: Material, rng: ptr<function, u32>) -> vec3<f32> {
+    if environment.enabled == 0u {
+        return vec3<f32>(0.0);
+    }
+    let light = sample_environment(rand_vec2f(rng));
+    if light.pdf <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let bsdf = eval_bsdf(material, n, wo, light.direction);
+    if all(bsdf.rgb <= vec3<f32>(0.0)) {
+        return vec3<f32>(0.0);
+    }
+    if scene_hit(Ray(p + n * rayOffset, light.direction)).hit.hit {
+        return vec3<f32>(0.0); // occluded
+    }
+    return light.radiance * bsdf.rgb * power_heuristic(light.pdf, bsdf.a) / light.pdf;
 }
 
-fn scatter(hitResult: RayResult, rng: ptr<function, u32>) -> ScatterRecord {
-    let hit = hitResult.hit;
-    let material = hitResult.material;
+// Solid angle density with which sample_light picks a direction towards the given sphere
+fn light_pdf(p: vec3<f32>, index: u32) -> f32 {
+    let light = spheres[index];
+    let to_center = light.pos - p;
+    let ratio = light.radius * light.radius / dot(to_center, to_center);
+    if ratio >= 1.0 {
+        return 0.0;
+    }
+    let one_minus_cos_max = ratio / (1.0 + sqrt(1.0 - ratio));
+    let light_count = details.sphere_count - details.first_light;
+    return 1.0 / (2.0 * PI * one_minus_cos_max * f32(light_count));
+}
 
-    let rand = normalize(rand_vec3f(rng) - vec3<f32>(0.5));
-    let zz = hit.p + hit.normal + material.roughness * rand;
-    let newray = Ray(hit.p, -normalize(hit.p - zz));
-    return ScatterRecord(newray, 0.5);
+fn power_heuristic(pdf: f32, other: f32) -> f32 {
+    let a = pdf * pdf;
+    return a / (a + other * other);
 }
 
 // fn castray(screen_pos: vec2<f32>, rng: ptr<function, u32>) -> vec4<f32> {
@@ -289,6 +611,20 @@ fn hit_sphere(center: vec3<f32>, radius: f32, r: Ray, near: f32, far: f32) -> Hi
     return Hit(true, root, p, normal, center);
 }
 
+// Entry distance into the box, a huge value on a miss
+fn hit_aabb(start: vec3<f32>, inv_direction: vec3<f32>, box_min: vec3<f32>, box_max: vec3<f32>) -> f32 {
+    let t0 = (box_min - start) * inv_direction;
+    let t1 = (box_max - start) * inv_direction;
+    let t_min = min(t0, t1);
+    let t_max = max(t0, t1);
+    let near = max(max(t_min.x, t_min.y), max(t_min.z, 0.0));
+    let far = min(min(t_max.x, t_max.y), t_max.z);
+    if near > far {
+        return 1e30;
+    }
+    return near;
+}
+
 fn intersectTriangle(ray: Ray, v0: vec3<f32>, v1: vec3<f32>, v2: vec3<f32>) -> f32 {
     let edge1 = v1 - v0;
     let edge2 = v2 - v0;
@@ -373,19 +709,4 @@ fn computeBarycentricCoords(p: vec3<f32>, a: vec3<f32>, b: vec3<f32>, c: vec3<f3
     return vec3<f32>(u, v, w);
 }
 
-fn linear_to_gamma(c: vec4<f32>) -> vec4<f32> {
-    return vec4<f32>(
-        linear_to_gamma_comp(c.r),
-        linear_to_gamma_comp(c.g),
-        linear_to_gamma_comp(c.b),
-        c.a
-    );
-}
-
-fn linear_to_gamma_comp(c: f32) -> f32 {
-    if c <= 0.0 {
-        return 0.0;
-    }
-    return sqrt(c);
-}
 
@@ -23,11 +23,65 @@ fn vs_main(
 
 // Fragment shader
 
+struct ToneMapping {
+    exposure: f32,    // linear scale
+    curve: u32,       // 0 clamp, 1 Reinhard, 2 ACES, 3 filmic
+    encode_srgb: u32, // the surface format doesn't convert by itself
+}
+
 @group(0) @binding(0) var s: sampler;
 @group(0) @binding(1) var t_input: texture_2d<f32>;
+@group(0) @binding(2) var<uniform> tone_mapping: ToneMapping;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_input, s, in.tex_coords);
+    let hdr = textureSample(t_input, s, in.tex_coords);
+    var color = tonemap(hdr.rgb * tone_mapping.exposure);
+    if tone_mapping.encode_srgb != 0u {
+        color = linear_to_srgb(color);
+    }
+    return vec4<f32>(color, hdr.a);
+}
+
+fn tonemap(c: vec3<f32>) -> vec3<f32> {
+    switch tone_mapping.curve {
+        case 1u: {
+            return c / (1.0 + c);
+        }
+        case 2u: {
+            return aces(c);
+        }
+        case 3u: {
+            let white = 11.2;
+            return hable(c * 2.0) / hable(vec3<f32>(white));
+        }
+        default: {
+            return clamp(c, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+    }
+}
+
+// Narkowicz's fit of the ACES reference rendering transform
+fn aces(c: vec3<f32>) -> vec3<f32> {
+    let a = c * (2.51 * c + 0.03);
+    let b = c * (2.43 * c + 0.59) + 0.14;
+    return clamp(a / b, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Hable's Uncharted 2 curve
+fn hable(c: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let cc = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return (c * (a * c + cc * b) + d * e) / (c * (a * c + b) + d * f) - e / f;
+}
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    let low = c * 12.92;
+    let high = 1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, c <= vec3<f32>(0.0031308));
 }
 
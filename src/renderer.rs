@@ -1,9 +1,29 @@
 use std::iter;
+use std::path::Path;
 use std::sync::Arc;
 
+use cgmath::{Matrix4, Vector3};
 use winit::window::Window;
 
-use crate::{camera::Camera, fpscounter::FPSCounter, pipelines::Pipelines};
+use crate::animation::CameraPath;
+use crate::environment::{EnvironmentMap, EnvironmentSettings};
+use crate::pipelines::denoise_pipeline::DenoiseSettings;
+use crate::pipelines::medical_pipeline::{BlendMode, MedicalMode};
+use crate::pipelines::raytrace_pipeline::TraceSettings;
+use crate::pipelines::sampletexture_pipeline::ToneMapping;
+use crate::scene::{Scene, SceneError, SceneWatcher, DEFAULT_SCENE_PATH};
+use crate::ron_file::{self, RonFileError};
+use crate::session::{MedicalState, RenderSettings, Session};
+use crate::camera::{AnatomicalView, Camera, CameraMode, CameraView, Projection};
+use crate::input::CameraInput;
+use crate::transfer_function::TransferFunction;
+use crate::{fpscounter::FPSCounter, pipelines::Pipelines};
+use crate::pipelines::{SurfaceSource, BONE_ISO_VALUE};
+use crate::mesh::export;
+#[cfg(web_platform)]
+use crate::mesh::export::MeshFormat;
+
+const SURFACE_EXPORT_PATH: &str = "surface.stl";
 
 pub struct Renderer {
     surface: wgpu::Surface<'static>,
@@ -12,9 +32,10 @@ pub struct Renderer {
     queue: wgpu::Queue,
     pipelines: Pipelines,
     fpscounter: FPSCounter,
-    multisample_framebuffer: wgpu::TextureView,
     depthbuffer: wgpu::TextureView,
     camera: Camera,
+    scene: Scene,
+    scene_watcher: Option<SceneWatcher>,
 }
 
 impl Renderer {
@@ -73,49 +94,181 @@ impl Renderer {
 
         let camera = Camera::new(&device, &surface_config);
 
-        let pipelines = Pipelines::new(&surface_config, &device, &queue, &camera);
-
-        let fpscounter = FPSCounter::new();
+        let depthbuffer = Renderer::create_depthbuffer(&device, &surface_config);
 
-        let multisample_framebuffer =
-            Renderer::create_multisampled_framebuffer(&device, &surface_config, 4);
+        let pipelines = Pipelines::new(&surface_config, &device, &queue, &camera, &depthbuffer);
 
-        let depthbuffer = Renderer::create_depthbuffer(&device, &surface_config);
+        let fpscounter = FPSCounter::new();
 
-        Self {
+        let mut renderer = Self {
             surface,
             device,
             queue,
             surface_config,
             pipelines,
             fpscounter,
-            multisample_framebuffer,
             depthbuffer,
             camera,
+            scene: Scene::default(),
+            scene_watcher: None,
+        };
+
+        if let Err(e) = renderer.open_scene(DEFAULT_SCENE_PATH) {
+            println!("{e}, using the built-in scene");
+            let scene = Scene::builtin();
+            renderer.set_scene(scene).expect("built-in scene has no meshes to fail on");
+        }
+
+        // The volume is inspected from the outside, orbiting its centre
+        if let Some(medical_pipeline) = &renderer.pipelines.medical_pipeline {
+            renderer.camera.set_bounds(medical_pipeline.volume_center(), medical_pipeline.volume_radius());
         }
+        if let Some((view, ortho_height)) = renderer.volume_framing(AnatomicalView::Anterior) {
+            renderer.camera.ortho_height = ortho_height;
+            renderer.camera.set_mode(CameraMode::Orbit);
+            renderer.camera.set_view(view);
+        }
+        renderer
     }
 
-    pub fn create_multisampled_framebuffer(
-        device: &wgpu::Device,
-        sc_desc: &wgpu::SurfaceConfiguration,
-        sample_count: u32,
-    ) -> wgpu::TextureView {
-        let multisampled_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Multisampled Framebuffer"),
-            size: wgpu::Extent3d {
-                width: sc_desc.width,
-                height: sc_desc.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count,
-            dimension: wgpu::TextureDimension::D2,
-            format: sc_desc.format,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[],
-        });
+    /// Loads a scene file into the ray tracer and reloads it whenever the file changes.
+    pub fn open_scene(&mut self, path: impl AsRef<Path>) -> Result<(), SceneError> {
+        let path = path.as_ref();
+        self.set_scene(Scene::open(path)?)?;
+        self.scene_watcher = Some(SceneWatcher::new(path));
+        Ok(())
+    }
+
+    /// Shows a scene in the ray tracer, and its overlays with the volume. Its environment lights
+    /// the volume as well.
+    pub fn set_scene(&mut self, scene: Scene) -> Result<(), SceneError> {
+        let environment = scene.load_environment()?;
+        let overlays = match &self.pipelines.medical_pipeline {
+            Some(medical_pipeline) => scene.load_overlays(medical_pipeline.volume_center().into())?,
+            None => Vec::new(),
+        };
+        self.pipelines.raytrace_pipeline.set_scene(&self.device, &self.queue, &scene)?;
+        self.pipelines.mesh_pipeline.set_overlays(&self.device, overlays);
+        self.set_environment(environment);
+        self.set_environment_settings(scene.environment.as_ref().map(|e| e.settings()).unwrap_or_default());
+        // The medical view frames the volume itself
+        if let (Some(camera), None) = (scene.camera, &self.pipelines.medical_pipeline) {
+            self.camera.look_at(camera.eye.into(), camera.target.into());
+            self.camera.fovy = camera.fovy;
+        }
+        self.scene = scene;
+        Ok(())
+    }
+
+    /// Lights the volume and the ray traced scene with an equirectangular `.hdr` image, until
+    /// the scene file is reloaded.
+    pub fn open_environment(&mut self, path: impl AsRef<Path>) -> Result<(), image::ImageError> {
+        self.set_environment(Some(EnvironmentMap::open(path)?));
+        Ok(())
+    }
+
+    /// `None` goes back to the default sky.
+    pub fn set_environment(&mut self, map: Option<EnvironmentMap>) {
+        let map = map.as_ref();
+        self.pipelines.raytrace_pipeline.set_environment(&self.device, &self.queue, map);
+        if let Some(medical_pipeline) = &mut self.pipelines.medical_pipeline {
+            medical_pipeline.set_environment(&self.device, &self.queue, map);
+        }
+    }
+
+    pub fn environment_settings(&self) -> EnvironmentSettings {
+        match &self.pipelines.medical_pipeline {
+            Some(medical_pipeline) => medical_pipeline.environment_settings(),
+            None => self.pipelines.raytrace_pipeline.environment_settings(),
+        }
+    }
+
+    pub fn set_environment_settings(&mut self, settings: EnvironmentSettings) {
+        self.pipelines.raytrace_pipeline.set_environment_settings(&self.queue, settings);
+        if let Some(medical_pipeline) = &mut self.pipelines.medical_pipeline {
+            medical_pipeline.set_environment_settings(&self.queue, settings);
+        }
+    }
+
+    /// Switches between MIP, DVR, slices and the cinematic path tracer.
+    pub fn cycle_medical_mode(&mut self) {
+        if let Some(medical_pipeline) = &mut self.pipelines.medical_pipeline {
+            medical_pipeline.settings.mode = match medical_pipeline.settings.mode {
+                MedicalMode::Mip => MedicalMode::Dvr,
+                MedicalMode::Dvr => MedicalMode::Mpr,
+                MedicalMode::Mpr => MedicalMode::Cinematic,
+                MedicalMode::Cinematic => MedicalMode::Mip,
+            };
+        }
+    }
+
+    /// Switches between alpha, additive and checkerboard fusion.
+    pub fn cycle_blend_mode(&mut self) {
+        if let Some(medical_pipeline) = &mut self.pipelines.medical_pipeline {
+            medical_pipeline.settings.blend_mode = match medical_pipeline.settings.blend_mode {
+                BlendMode::Alpha => BlendMode::Additive,
+                BlendMode::Additive => BlendMode::Checkerboard,
+                BlendMode::Checkerboard => BlendMode::Alpha,
+            };
+        }
+    }
+
+    /// Moves the fusion volume relative to the primary one, in patient space (mm).
+    pub fn shift_fusion(&mut self, offset: Vector3<f32>) {
+        if let Some(medical_pipeline) = &mut self.pipelines.medical_pipeline {
+            medical_pipeline.fusion_registration =
+                Matrix4::from_translation(offset) * medical_pipeline.fusion_registration;
+        }
+    }
 
-        multisampled_texture.create_view(&wgpu::TextureViewDescriptor::default())
+    /// Moves the iso value of the surface (HU), starting from the bones if a structure was shown.
+    pub fn shift_iso_value(&mut self, delta: f32) {
+        let iso = match self.pipelines.surface_source() {
+            Some(SurfaceSource::IsoValue(iso)) => iso + delta,
+            _ => BONE_ISO_VALUE,
+        };
+        self.pipelines.set_surface(&self.device, &self.queue, SurfaceSource::IsoValue(iso));
+    }
+
+    /// Shows the surface of the next structure of the segmentation, after the last one the bones.
+    pub fn cycle_surface_label(&mut self) {
+        let Some(medical_pipeline) = &self.pipelines.medical_pipeline else {
+            return;
+        };
+        let label_count = medical_pipeline.labels.len() as u32;
+        if label_count < 2 {
+            return;
+        }
+        let source = match self.pipelines.surface_source() {
+            Some(SurfaceSource::Label(label)) if label + 1 < label_count => SurfaceSource::Label(label + 1),
+            Some(SurfaceSource::Label(_)) => SurfaceSource::IsoValue(BONE_ISO_VALUE),
+            _ => SurfaceSource::Label(1),
+        };
+        self.pipelines.set_surface(&self.device, &self.queue, source);
+    }
+
+    /// Writes the extracted surface to an STL file, in the browser as a download.
+    pub fn export_surface(&self) {
+        let Some(mesh) = self.pipelines.surface_mesh() else {
+            println!("no surface to export");
+            return;
+        };
+        #[cfg(not(web_platform))]
+        match export::save(mesh, SURFACE_EXPORT_PATH) {
+            Ok(()) => println!("saved {} triangles to {SURFACE_EXPORT_PATH}", mesh.triangle_count()),
+            Err(e) => println!("cannot save {SURFACE_EXPORT_PATH}: {e}"),
+        }
+        #[cfg(web_platform)]
+        if let Err(e) = export::download(mesh, MeshFormat::StlBinary, SURFACE_EXPORT_PATH) {
+            println!("cannot download {SURFACE_EXPORT_PATH}: {e:?}");
+        }
+    }
+
+    /// Shows or hides one structure of the segmentation, if one is loaded.
+    pub fn toggle_label(&mut self, label: u32) {
+        if let Some(medical_pipeline) = &mut self.pipelines.medical_pipeline {
+            medical_pipeline.toggle_label(label);
+        }
     }
 
     pub fn create_depthbuffer(
@@ -133,7 +286,8 @@ impl Renderer {
             sample_count: 4,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Depth32Float,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            // Sampled by the volume passes to stop rays at mesh surfaces
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
         });
 
@@ -155,9 +309,9 @@ impl Renderer {
         self.pipelines.render(
             &self.device,
             &output_view,
-            &self.multisample_framebuffer,
             &self.depthbuffer,
             &mut encoder,
+            &self.camera,
         );
 
         self.queue.submit(iter::once(encoder.finish()));
@@ -168,46 +322,222 @@ impl Renderer {
         Ok(())
     }
 
-    pub fn update(&mut self) {
-        self.camera.update(&self.queue);
+    pub fn trace_settings(&self) -> TraceSettings {
+        self.pipelines.raytrace_pipeline.settings()
+    }
+
+    pub fn set_trace_settings(&mut self, settings: TraceSettings) {
+        self.pipelines.raytrace_pipeline.set_settings(settings);
+    }
+
+    pub fn denoise_settings(&self) -> DenoiseSettings {
+        self.pipelines.denoise_settings()
+    }
+
+    pub fn set_denoise_settings(&mut self, settings: DenoiseSettings) {
+        self.pipelines.set_denoise_settings(settings);
+    }
+
+    pub fn tone_mapping(&self) -> ToneMapping {
+        self.pipelines.tone_mapping()
+    }
+
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.pipelines.set_tone_mapping(&self.queue, tone_mapping);
+    }
+
+    pub fn render_settings(&self) -> RenderSettings {
+        RenderSettings {
+            tone_mapping: self.tone_mapping(),
+            trace: self.trace_settings(),
+            denoise: self.denoise_settings(),
+            environment: self.environment_settings(),
+            medical: self.pipelines.medical_pipeline.as_ref().map(|medical_pipeline| MedicalState {
+                settings: medical_pipeline.settings.clone(),
+                lighting: medical_pipeline.lighting,
+                cinematic: medical_pipeline.cinematic.clone(),
+                transfer_function: medical_pipeline.transfer_function.clone(),
+                fusion_transfer_function: medical_pipeline.fusion_transfer_function.clone(),
+                labels: medical_pipeline.labels.clone(),
+                fusion_registration: medical_pipeline.fusion_registration.into(),
+            }),
+        }
+    }
+
+    pub fn set_render_settings(&mut self, settings: &RenderSettings) {
+        self.set_tone_mapping(settings.tone_mapping);
+        self.set_trace_settings(settings.trace);
+        self.set_denoise_settings(settings.denoise);
+        self.set_environment_settings(settings.environment);
+        if let (Some(medical_pipeline), Some(medical)) = (&mut self.pipelines.medical_pipeline, &settings.medical) {
+            medical_pipeline.settings = medical.settings.clone();
+            medical_pipeline.lighting = medical.lighting;
+            medical_pipeline.cinematic = medical.cinematic.clone();
+            medical_pipeline.labels.restore(&medical.labels);
+            medical_pipeline.fusion_registration = medical.fusion_registration.into();
+            // A hand edited file may leave them empty
+            if !medical.transfer_function.points.is_empty() {
+                medical_pipeline.transfer_function = TransferFunction::new(medical.transfer_function.points.clone());
+            }
+            if !medical.fusion_transfer_function.points.is_empty() {
+                medical_pipeline.fusion_transfer_function =
+                    TransferFunction::new(medical.fusion_transfer_function.points.clone());
+            }
+        }
+    }
+
+    pub fn session(&self) -> Session {
+        Session {
+            camera: Some(self.camera.state()),
+            render: self.render_settings(),
+        }
+    }
+
+    pub fn restore_session(&mut self, session: &Session) {
+        if let Some(camera) = &session.camera {
+            self.camera.set_state(camera);
+        }
+        self.set_render_settings(&session.render);
+    }
+
+    pub fn open_session(&mut self, path: impl AsRef<Path>) -> Result<(), RonFileError> {
+        self.restore_session(&ron_file::load(path)?);
+        Ok(())
+    }
+
+    pub fn save_session(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        ron_file::save(&self.session(), path)
+    }
+
+    pub fn update(&mut self, input: &CameraInput) {
+        if let Some(result) = self.scene_watcher.as_mut().and_then(SceneWatcher::poll) {
+            if let Err(e) = result.and_then(|scene| self.set_scene(scene)) {
+                println!("{e}, keeping the previous scene");
+            }
+        }
+
+        self.camera.update(&self.queue, input);
+        self.pipelines.update(&self.queue, &self.camera);
+    }
+
+    pub fn camera_mode(&self) -> CameraMode {
+        self.camera.mode
+    }
+
+    pub fn set_camera_mode(&mut self, mode: CameraMode) {
+        self.camera.set_mode(mode);
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.camera.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.camera.set_projection(projection);
+    }
+
+    /// Turns the camera around its pivot to look at the volume from one side.
+    pub fn view_from(&mut self, side: AnatomicalView) {
+        if self.pipelines.medical_pipeline.is_none() {
+            return;
+        }
+        let (forward, up) = side.orientation();
+        let view = CameraView { forward, up, ..self.camera.view() };
+        self.camera.set_mode(CameraMode::Orbit);
+        self.camera.animate_to(view);
+    }
+
+    /// Frames the whole volume from the front, or goes back to the scene file's camera.
+    pub fn reset_view(&mut self) {
+        match self.volume_framing(AnatomicalView::Anterior) {
+            Some((view, ortho_height)) => {
+                self.camera.ortho_height = ortho_height;
+                self.camera.set_mode(CameraMode::Orbit);
+                self.camera.animate_to(view);
+            }
+            None => {
+                if let Some(camera) = self.scene.camera {
+                    self.camera.up = -cgmath::Vector3::unit_y();
+                    self.camera.look_at(camera.eye.into(), camera.target.into());
+                    self.camera.fovy = camera.fovy;
+                }
+            }
+        }
+    }
+
+    // Looking at the centre of the volume from `side`, far enough for all of it to be visible,
+    // and the orthographic view height that shows all of it
+    fn volume_framing(&self, side: AnatomicalView) -> Option<(CameraView, f32)> {
+        let medical_pipeline = self.pipelines.medical_pipeline.as_ref()?;
+        let (forward, up) = side.orientation();
+        let radius = medical_pipeline.volume_radius();
+        let view = CameraView {
+            pivot: medical_pipeline.volume_center(),
+            forward,
+            up,
+            distance: self.camera.framing_distance(radius),
+        };
+        Some((view, self.camera.framing_height(radius)))
+    }
+
+    /// Circles the camera around its pivot in `duration` seconds until stopped.
+    pub fn turntable(&mut self, duration: f32) {
+        self.camera.set_mode(CameraMode::Orbit);
+        let path = CameraPath::turntable(self.camera.pivot(), self.camera.eye, -self.camera.up, self.camera.fovy, duration);
+        self.camera.play(path);
+    }
+
+    /// Plays back a keyframe file, e.g. one saved from a recording.
+    pub fn open_camera_path(&mut self, path: impl AsRef<Path>) -> Result<(), RonFileError> {
+        self.camera.play(ron_file::load(path)?);
+        Ok(())
+    }
+
+    pub fn play_camera_path(&mut self, path: CameraPath) {
+        self.camera.play(path);
+    }
+
+    pub fn stop_camera_path(&mut self) {
+        self.camera.stop_playback();
     }
 
-    // Mouse input forwarding to camera
-    pub fn mouse_motion(&mut self, dx: f32, dy: f32) {
-        self.camera.mouse_motion(dx, dy);
+    pub fn is_playing_camera_path(&self) -> bool {
+        self.camera.is_playing()
     }
 
-    pub fn mouse_buttons(&mut self, right: bool, middle: bool) {
-        self.camera.mouse_buttons(right, middle);
+    pub fn start_recording(&mut self) {
+        self.camera.start_recording();
     }
 
-    pub fn mouse_wheel(&mut self, delta: f32) {
-        self.camera.mouse_wheel(delta);
+    pub fn stop_recording(&mut self) -> Option<CameraPath> {
+        self.camera.stop_recording()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.camera.is_recording()
     }
 
     pub fn get_fps(&self) -> String {
         self.fpscounter.print()
     }
 
+    /// Follows the window size. Also reconfigures a lost or outdated surface when the size
+    /// didn't change.
     pub fn resize(&mut self, width: u32, height: u32) {
-        if width <= 0 && height <= 0 {
+        if width == 0 || height == 0 {
             return;
         }
 
+        let resized = (width, height) != (self.surface_config.width, self.surface_config.height);
         self.surface_config.width = width;
         self.surface_config.height = height;
         self.surface.configure(&self.device, &self.surface_config);
+        if !resized {
+            return;
+        }
 
-        self.multisample_framebuffer =
-            Renderer::create_multisampled_framebuffer(&self.device, &self.surface_config, 4);
         self.depthbuffer = Renderer::create_depthbuffer(&self.device, &self.surface_config);
-
-        self.camera = Camera::new(&self.device, &self.surface_config);
-        self.pipelines = Pipelines::new(
-            &self.surface_config,
-            &self.device,
-            &self.queue,
-            &self.camera,
-        );
+        self.camera.resize(width, height);
+        self.pipelines.resize(&self.surface_config, &self.device, &self.depthbuffer);
     }
 }
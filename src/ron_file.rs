@@ -0,0 +1,37 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Reading a scene, session, camera path or bindings file failed.
+#[derive(Debug)]
+pub enum RonFileError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, ron::error::SpannedError),
+}
+
+pub fn load<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<T, RonFileError> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|e| RonFileError::Io(path.to_path_buf(), e))?;
+    ron::from_str(&text).map_err(|e| RonFileError::Parse(path.to_path_buf(), e))
+}
+
+pub fn to_string<T: Serialize>(value: &T) -> String {
+    ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default()).expect("plain data serializes")
+}
+
+pub fn save<T: Serialize>(value: &T, path: impl AsRef<Path>) -> std::io::Result<()> {
+    std::fs::write(path, to_string(value))
+}
+
+impl fmt::Display for RonFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RonFileError::Io(path, e) => write!(f, "cannot read {}: {e}", path.display()),
+            RonFileError::Parse(path, e) => write!(f, "invalid {}: {e}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for RonFileError {}
@@ -0,0 +1,220 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::environment::{EnvironmentMap, EnvironmentSettings};
+use crate::mesh::gltf_scene::{GltfScene, SceneMesh};
+use crate::mesh::Material;
+use crate::ron_file::{self, RonFileError};
+
+pub const DEFAULT_SCENE_PATH: &str = "assets/scenes/default.ron";
+
+/// Everything the ray tracer draws, as described by a RON scene file, and the models shown
+/// with a volume.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scene {
+    pub camera: Option<SceneCamera>,
+    pub spheres: Vec<Sphere>,
+    pub meshes: Vec<MeshInstance>,
+    pub lights: Vec<Light>,
+    pub environment: Option<SceneEnvironment>, // replaces the white sky
+    // Rasterized with the volume instead, e.g. an implant or a tool. Sizes are in mm and the
+    // translation is relative to the volume centre.
+    pub overlays: Vec<MeshInstance>,
+    #[serde(skip)]
+    pub base_dir: PathBuf, // mesh paths are relative to the scene file
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Sphere {
+    pub center: [f32; 3],
+    pub radius: f32,
+    #[serde(default)]
+    pub material: Material,
+}
+
+/// A glTF file placed in the scene with a uniform scale and an offset.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MeshInstance {
+    pub path: String,
+    #[serde(default)]
+    pub translation: [f32; 3],
+    #[serde(default = "unit_scale")]
+    pub scale: f32,
+    #[serde(default)]
+    pub material: Option<Material>, // replaces the materials from the file
+}
+
+/// Spherical area light, traced as an emissive sphere.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Light {
+    pub position: [f32; 3],
+    pub radius: f32,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+/// Equirectangular `.hdr` image lighting the scene from all directions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SceneEnvironment {
+    pub path: String,
+    #[serde(default = "unit_scale")]
+    pub intensity: f32,
+    #[serde(default)]
+    pub rotation: f32, // degrees around the up axis
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct SceneCamera {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    #[serde(default = "default_fovy")]
+    pub fovy: f32, // degrees
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    File(RonFileError),
+    Mesh(PathBuf, gltf::Error),
+    Environment(PathBuf, image::ImageError),
+}
+
+fn unit_scale() -> f32 {
+    1.0
+}
+
+fn default_fovy() -> f32 {
+    45.0
+}
+
+impl Scene {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        let mut scene: Scene = ron_file::load(path).map_err(SceneError::File)?;
+        scene.base_dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        Ok(scene)
+    }
+
+    /// The default scene compiled into the binary, for the web and missing asset folders. The
+    /// files it refers to are missing there too, so it keeps only the spheres and lights.
+    pub fn builtin() -> Self {
+        let mut scene: Scene =
+            ron::from_str(include_str!("../assets/scenes/default.ron")).expect("built-in scene is valid");
+        scene.meshes.clear();
+        scene.overlays.clear();
+        scene.environment = None;
+        scene
+    }
+
+    /// Reads the referenced glTF files, baked to world space with their instance transform.
+    pub fn load_meshes(&self) -> Result<Vec<SceneMesh>, SceneError> {
+        self.load_instances(&self.meshes, [0.0; 3])
+    }
+
+    /// Reads the models shown with a volume, placed relative to its centre.
+    pub fn load_overlays(&self, volume_center: [f32; 3]) -> Result<Vec<SceneMesh>, SceneError> {
+        self.load_instances(&self.overlays, volume_center)
+    }
+
+    fn load_instances(&self, instances: &[MeshInstance], origin: [f32; 3]) -> Result<Vec<SceneMesh>, SceneError> {
+        let mut meshes = Vec::new();
+        for instance in instances {
+            let path = self.base_dir.join(&instance.path);
+            let gltf = GltfScene::open(&path).map_err(|e| SceneError::Mesh(path, e))?;
+            for mut scene_mesh in gltf.meshes {
+                for vertex in &mut scene_mesh.mesh.vertices {
+                    for ((p, t), o) in vertex.position.iter_mut().zip(instance.translation).zip(origin) {
+                        *p = *p * instance.scale + t + o;
+                    }
+                }
+                if let Some(material) = instance.material {
+                    scene_mesh.material = material;
+                }
+                meshes.push(scene_mesh);
+            }
+        }
+        Ok(meshes)
+    }
+
+    pub fn load_environment(&self) -> Result<Option<EnvironmentMap>, SceneError> {
+        let Some(environment) = &self.environment else {
+            return Ok(None);
+        };
+        let path = self.base_dir.join(&environment.path);
+        EnvironmentMap::open(&path)
+            .map(Some)
+            .map_err(|e| SceneError::Environment(path, e))
+    }
+}
+
+impl SceneEnvironment {
+    pub fn settings(&self) -> EnvironmentSettings {
+        EnvironmentSettings {
+            intensity: self.intensity,
+            rotation: self.rotation,
+        }
+    }
+}
+
+impl Light {
+    pub fn sphere(&self) -> Sphere {
+        let c = self.color;
+        Sphere {
+            center: self.position,
+            radius: self.radius,
+            material: Material {
+                base_color: [0.0, 0.0, 0.0, 1.0],
+                roughness: 1.0,
+                emissive: [c[0] * self.intensity, c[1] * self.intensity, c[2] * self.intensity],
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::File(e) => write!(f, "{e}"),
+            SceneError::Mesh(path, e) => write!(f, "cannot load {}: {e}", path.display()),
+            SceneError::Environment(path, e) => write!(f, "cannot load {}: {e}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// Watches a scene file by its modification time, so edits show up without a restart.
+pub struct SceneWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl SceneWatcher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let modified = modified_time(&path);
+        SceneWatcher { path, modified }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The reloaded scene if the file changed since the last call.
+    pub fn poll(&mut self) -> Option<Result<Scene, SceneError>> {
+        let modified = modified_time(&self.path)?;
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+        Some(Scene::open(&self.path))
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
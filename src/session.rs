@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+use crate::camera::CameraState;
+use crate::environment::EnvironmentSettings;
+use crate::labelmap::LabelMap;
+use crate::pipelines::denoise_pipeline::DenoiseSettings;
+use crate::pipelines::medical_pipeline::{CinematicSettings, LightingSettings, MedicalSettings};
+use crate::pipelines::raytrace_pipeline::TraceSettings;
+use crate::pipelines::sampletexture_pipeline::ToneMapping;
+use crate::transfer_function::TransferFunction;
+
+pub const DEFAULT_SESSION_PATH: &str = "session.ron";
+
+/// The view and the render settings of a viewer, saved as RON to continue where it was left.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub camera: Option<CameraState>,
+    pub render: RenderSettings,
+}
+
+/// Everything the user can tune about the image, independent of the window size.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderSettings {
+    pub tone_mapping: ToneMapping,
+    pub trace: TraceSettings,
+    pub denoise: DenoiseSettings,
+    pub environment: EnvironmentSettings,
+    pub medical: Option<MedicalState>, // only with a volume loaded
+}
+
+/// Display mode, window/level, lighting and classification of the volume.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MedicalState {
+    #[serde(default)]
+    pub settings: MedicalSettings,
+    #[serde(default)]
+    pub lighting: LightingSettings, // DVR shadows and ambient occlusion
+    #[serde(default)]
+    pub cinematic: CinematicSettings,
+    pub transfer_function: TransferFunction,
+    pub fusion_transfer_function: TransferFunction,
+    #[serde(default)]
+    pub labels: LabelMap, // colour, opacity and visibility per label
+    #[serde(default = "identity")]
+    pub fusion_registration: [[f32; 4]; 4], // column major, fusion voxels to CT patient space
+}
+
+fn identity() -> [[f32; 4]; 4] {
+    [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipelines::medical_pipeline::MedicalMode;
+
+    #[test]
+    fn sessions_with_missing_settings_load_with_defaults() {
+        let session: Session = ron::from_str("(render: (denoise: (iterations: 2), tone_mapping: (exposure: 1.0)))").unwrap();
+        assert_eq!(session.render.denoise.iterations, 2);
+        assert!(session.render.denoise.enabled);
+        assert_eq!(session.render.tone_mapping.exposure, 1.0);
+
+        let settings: MedicalSettings = ron::from_str("(mode: Dvr, window_width: 80.0)").unwrap();
+        assert_eq!(settings.mode, MedicalMode::Dvr);
+        assert_eq!(settings.window_width, 80.0);
+        assert_eq!(settings.window_center, MedicalSettings::default().window_center);
+    }
+}
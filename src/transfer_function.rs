@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+
+/// A piecewise-linear colour/opacity mapping from scalar values, baked into a fixed size
+/// lookup table for the shaders.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransferFunction {
+    pub points: Vec<ControlPoint>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ControlPoint {
+    pub value: f32,
+    pub color: [f32; 4], // rgb + opacity
+}
+
+impl TransferFunction {
+    pub const RESOLUTION: usize = 256;
+
+    pub fn new(mut points: Vec<ControlPoint>) -> Self {
+        assert!(!points.is_empty(), "transfer function needs at least one point");
+        points.sort_by(|a, b| a.value.total_cmp(&b.value));
+        Self { points }
+    }
+
+    /// Soft tissue in red, bone in white (Hounsfield units).
+    pub fn ct_default() -> Self {
+        TransferFunction::new(vec![
+            ControlPoint { value: -1000.0, color: [0.0, 0.0, 0.0, 0.0] },
+            ControlPoint { value: -100.0, color: [0.0, 0.0, 0.0, 0.0] },
+            ControlPoint { value: 0.0, color: [0.75, 0.3, 0.2, 0.02] },
+            ControlPoint { value: 200.0, color: [0.9, 0.6, 0.4, 0.05] },
+            ControlPoint { value: 400.0, color: [1.0, 1.0, 0.9, 0.4] },
+            ControlPoint { value: 1500.0, color: [1.0, 1.0, 1.0, 0.8] },
+        ])
+    }
+
+    /// Grayscale ramp over the given range, opacity rising with intensity.
+    pub fn ramp(min: f32, max: f32) -> Self {
+        TransferFunction::new(vec![
+            ControlPoint { value: min, color: [0.0, 0.0, 0.0, 0.0] },
+            ControlPoint { value: max, color: [1.0, 1.0, 1.0, 1.0] },
+        ])
+    }
+
+    /// Black-red-yellow-white colour scale commonly used for PET uptake.
+    pub fn hot_metal(min: f32, max: f32) -> Self {
+        let at = |t: f32| min + t * (max - min);
+        TransferFunction::new(vec![
+            ControlPoint { value: at(0.0), color: [0.0, 0.0, 0.0, 0.0] },
+            ControlPoint { value: at(0.35), color: [0.7, 0.0, 0.0, 0.3] },
+            ControlPoint { value: at(0.7), color: [1.0, 0.7, 0.0, 0.7] },
+            ControlPoint { value: at(1.0), color: [1.0, 1.0, 1.0, 1.0] },
+        ])
+    }
+
+    pub fn range(&self) -> (f32, f32) {
+        let min = self.points[0].value;
+        let max = self.points[self.points.len() - 1].value;
+        (min, max.max(min + 1e-3))
+    }
+
+    pub fn evaluate(&self, value: f32) -> [f32; 4] {
+        let first = &self.points[0];
+        if value <= first.value {
+            return first.color;
+        }
+        for pair in self.points.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            if value <= b.value {
+                let t = (value - a.value) / (b.value - a.value).max(1e-6);
+                let mut color = [0.0; 4];
+                for (i, c) in color.iter_mut().enumerate() {
+                    *c = a.color[i] + (b.color[i] - a.color[i]) * t;
+                }
+                return color;
+            }
+        }
+        self.points[self.points.len() - 1].color
+    }
+
+    /// Samples the function uniformly over `range()`.
+    pub fn bake(&self) -> Vec<[f32; 4]> {
+        let (min, max) = self.range();
+        (0..TransferFunction::RESOLUTION)
+            .map(|i| {
+                let t = i as f32 / (TransferFunction::RESOLUTION - 1) as f32;
+                self.evaluate(min + t * (max - min))
+            })
+            .collect()
+    }
+}
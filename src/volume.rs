@@ -0,0 +1,349 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use cgmath::{InnerSpace, Matrix4, Quaternion, SquareMatrix, Vector3, Vector4};
+use nifti::{IntoNdArray, NiftiError, NiftiHeader, NiftiObject, ReaderOptions};
+
+// Relative difference up to which two voxel-to-patient transforms count as the same, NIfTI
+// writers round the affine to single precision
+const TRANSFORM_TOLERANCE: f32 = 1e-4;
+
+/// A scalar volume in voxel order (x fastest) together with its voxel-to-patient transform.
+pub struct Volume {
+    pub dims: [u32; 3],
+    pub voxel_to_patient: Matrix4<f32>,
+    pub data: Vec<f32>,
+}
+
+#[derive(Debug)]
+pub enum VolumeError {
+    Nifti(PathBuf, NiftiError),
+    SingularTransform, // the voxel-to-patient transform has no inverse
+    GridMismatch { expected: [u32; 3], found: [u32; 3] },
+    TransformMismatch { expected: Box<[[f32; 4]; 4]>, found: Box<[[f32; 4]; 4]> }, // columns
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct VolumeUniform {
+    voxel_to_patient: [[f32; 4]; 4],
+    patient_to_voxel: [[f32; 4]; 4],
+    dims: [f32; 4],
+}
+
+impl VolumeUniform {
+    pub fn new(dims: [u32; 3], voxel_to_patient: Matrix4<f32>) -> Result<Self, VolumeError> {
+        let patient_to_voxel = voxel_to_patient.invert().ok_or(VolumeError::SingularTransform)?;
+        Ok(Self {
+            voxel_to_patient: voxel_to_patient.into(),
+            patient_to_voxel: patient_to_voxel.into(),
+            dims: [dims[0] as f32, dims[1] as f32, dims[2] as f32, 0.0],
+        })
+    }
+}
+
+impl Volume {
+    pub fn from_nifti(path: impl AsRef<Path>) -> Result<Self, VolumeError> {
+        let path = path.as_ref();
+        let nifti_error = |e| VolumeError::Nifti(path.to_path_buf(), e);
+        let obj = ReaderOptions::new().read_file(path).map_err(nifti_error)?;
+        let voxel_to_patient = Volume::header_affine(obj.header());
+        if voxel_to_patient.invert().is_none() {
+            return Err(VolumeError::SingularTransform);
+        }
+        let volume = obj.into_volume().into_ndarray::<f32>().map_err(nifti_error)?;
+
+        // 2D images are a single slice, time series keep their first frame
+        let shape = volume.shape();
+        let dims = [0, 1, 2].map(|axis| shape.get(axis).copied().unwrap_or(1) as u32);
+        let mut data = volume.into_raw_vec();
+        data.truncate((dims[0] * dims[1] * dims[2]) as usize);
+
+        Ok(Self {
+            dims,
+            voxel_to_patient,
+            data,
+        })
+    }
+
+    // Same precedence as the NIfTI spec: sform, then qform, then plain voxel spacing
+    fn header_affine(header: &NiftiHeader) -> Matrix4<f32> {
+        if header.sform_code != 0 {
+            let (x, y, z) = (header.srow_x, header.srow_y, header.srow_z);
+            return Matrix4::from_cols(
+                Vector4::new(x[0], y[0], z[0], 0.0),
+                Vector4::new(x[1], y[1], z[1], 0.0),
+                Vector4::new(x[2], y[2], z[2], 0.0),
+                Vector4::new(x[3], y[3], z[3], 1.0),
+            );
+        }
+
+        let spacing = Vector3::new(
+            header.pixdim[1].abs().max(1e-6),
+            header.pixdim[2].abs().max(1e-6),
+            header.pixdim[3].abs().max(1e-6),
+        );
+
+        if header.qform_code != 0 {
+            let (b, c, d) = (header.quatern_b, header.quatern_c, header.quatern_d);
+            let a = (1.0 - (b * b + c * c + d * d)).max(0.0).sqrt();
+            let rotation = Matrix4::from(Quaternion::new(a, b, c, d));
+            let qfac = if header.pixdim[0] < 0.0 { -1.0 } else { 1.0 };
+            let translation = Vector3::new(header.quatern_x, header.quatern_y, header.quatern_z);
+            return Matrix4::from_translation(translation)
+                * rotation
+                * Matrix4::from_nonuniform_scale(spacing.x, spacing.y, spacing.z * qfac);
+        }
+
+        Matrix4::from_nonuniform_scale(spacing.x, spacing.y, spacing.z)
+    }
+
+    /// Single background voxel, bound in place of an optional volume that isn't loaded.
+    pub fn placeholder() -> Self {
+        Self {
+            dims: [1, 1, 1],
+            voxel_to_patient: Matrix4::identity(),
+            data: vec![0.0],
+        }
+    }
+
+    pub fn voxel_count(&self) -> usize {
+        (self.dims[0] * self.dims[1] * self.dims[2]) as usize
+    }
+
+    pub fn value(&self, x: u32, y: u32, z: u32) -> f32 {
+        let index = x + self.dims[0] * (y + self.dims[1] * z);
+        self.data[index as usize]
+    }
+
+    pub fn range(&self) -> (f32, f32) {
+        self.data
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), v| (lo.min(*v), hi.max(*v)))
+    }
+
+    /// Physical size of a voxel along each axis, in patient units (mm).
+    pub fn spacing(&self) -> Vector3<f32> {
+        let m = self.voxel_to_patient;
+        Vector3::new(
+            m.x.truncate().magnitude(),
+            m.y.truncate().magnitude(),
+            m.z.truncate().magnitude(),
+        )
+    }
+
+    /// Centre of the volume in patient coordinates.
+    pub fn center(&self) -> Vector3<f32> {
+        Volume::grid_center(self.dims, self.voxel_to_patient)
+    }
+
+    /// Centre of a voxel grid in patient coordinates, for volumes no longer kept on the CPU.
+    pub fn grid_center(dims: [u32; 3], voxel_to_patient: Matrix4<f32>) -> Vector3<f32> {
+        let c = dims.map(|d| (d as f32 - 1.0) * 0.5);
+        (voxel_to_patient * Vector4::new(c[0], c[1], c[2], 1.0)).truncate()
+    }
+
+    pub fn uniform(&self) -> Result<VolumeUniform, VolumeError> {
+        VolumeUniform::new(self.dims, self.voxel_to_patient)
+    }
+
+    /// Fails unless `other` has the same voxel grid in the same place, as a segmentation of
+    /// this volume must.
+    pub fn check_grid(&self, other: &Volume) -> Result<(), VolumeError> {
+        if other.dims != self.dims {
+            return Err(VolumeError::GridMismatch {
+                expected: self.dims,
+                found: other.dims,
+            });
+        }
+        let expected: [[f32; 4]; 4] = self.voxel_to_patient.into();
+        let found: [[f32; 4]; 4] = other.voxel_to_patient.into();
+        let same = expected
+            .iter()
+            .flatten()
+            .zip(found.iter().flatten())
+            .all(|(a, b)| (a - b).abs() <= TRANSFORM_TOLERANCE * a.abs().max(1.0));
+        if !same {
+            return Err(VolumeError::TransformMismatch {
+                expected: Box::new(expected),
+                found: Box::new(found),
+            });
+        }
+        Ok(())
+    }
+
+    fn extent(&self) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            width: self.dims[0],
+            height: self.dims[1],
+            depth_or_array_layers: self.dims[2],
+        }
+    }
+
+    pub fn create_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Medical Volume Texture"),
+            size: self.extent(),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: Default::default(),
+        });
+
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(&self.data),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(self.dims[0] * 4),
+                rows_per_image: Some(self.dims[1]),
+            },
+            self.extent(),
+        );
+
+        texture
+    }
+
+    /// Uploads the volume as half floats rescaled from `range()` to 0..1, which unlike `R32Float`
+    /// can be sampled with linear filtering on every backend.
+    pub fn create_normalized_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::Texture {
+        let (min, max) = self.range();
+        let scale = 1.0 / (max - min).max(1e-6);
+        let values: Vec<u16> = self
+            .data
+            .iter()
+            .map(|v| half::f16::from_f32((v - min) * scale).to_bits())
+            .collect();
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Normalized Volume Texture"),
+            size: self.extent(),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::R16Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: Default::default(),
+        });
+
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(&values),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(self.dims[0] * 2),
+                rows_per_image: Some(self.dims[1]),
+            },
+            self.extent(),
+        );
+
+        texture
+    }
+
+    /// Uploads the volume as integer labels (rounded, clamped to `u16`).
+    pub fn create_label_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::Texture {
+        let labels: Vec<u16> = self
+            .data
+            .iter()
+            .map(|v| v.round().clamp(0.0, u16::MAX as f32) as u16)
+            .collect();
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Label Volume Texture"),
+            size: self.extent(),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::R16Uint,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: Default::default(),
+        });
+
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(&labels),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(self.dims[0] * 2),
+                rows_per_image: Some(self.dims[1]),
+            },
+            self.extent(),
+        );
+
+        texture
+    }
+
+    /// Highest label value present, used to size the label lookup table.
+    pub fn max_label(&self) -> u32 {
+        self.range().1.round().clamp(0.0, u16::MAX as f32) as u32
+    }
+}
+
+impl fmt::Display for VolumeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VolumeError::Nifti(path, e) => write!(f, "cannot read {}: {e}", path.display()),
+            VolumeError::SingularTransform => write!(f, "the voxel-to-patient transform is singular"),
+            VolumeError::GridMismatch { expected, found } => {
+                write!(f, "expected a {expected:?} voxel grid, found {found:?}")
+            }
+            VolumeError::TransformMismatch { expected, found } => {
+                write!(f, "expected the voxel-to-patient transform {expected:?}, found {found:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for VolumeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume(dims: [u32; 3], voxel_to_patient: Matrix4<f32>) -> Volume {
+        let count = (dims[0] * dims[1] * dims[2]) as usize;
+        Volume { dims, voxel_to_patient, data: vec![0.0; count] }
+    }
+
+    fn scan_transform() -> Matrix4<f32> {
+        let origin = Vector3::new(-250.0, -180.5, -420.0);
+        Matrix4::from_translation(origin) * Matrix4::from_nonuniform_scale(0.98, 0.98, 3.0)
+    }
+
+    #[test]
+    fn segmentation_on_the_same_grid_passes() {
+        let scan = volume([4, 5, 6], scan_transform());
+        // Rounding in the file doesn't count as a different grid
+        let rounded = scan_transform() * Matrix4::from_scale(1.0 + 1e-6);
+        assert!(scan.check_grid(&volume([4, 5, 6], rounded)).is_ok());
+    }
+
+    #[test]
+    fn segmentation_of_another_size_fails() {
+        let scan = volume([4, 5, 6], scan_transform());
+        let result = scan.check_grid(&volume([4, 5, 7], scan_transform()));
+        assert!(matches!(result, Err(VolumeError::GridMismatch { found: [4, 5, 7], .. })));
+    }
+
+    #[test]
+    fn segmentation_in_another_place_fails() {
+        let scan = volume([4, 5, 6], scan_transform());
+        let shifted = Matrix4::from_translation(Vector3::new(0.0, 0.0, 3.0)) * scan_transform();
+        let result = scan.check_grid(&volume([4, 5, 6], shifted));
+        assert!(matches!(result, Err(VolumeError::TransformMismatch { .. })));
+        let flipped = scan_transform() * Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0);
+        let result = scan.check_grid(&volume([4, 5, 6], flipped));
+        assert!(matches!(result, Err(VolumeError::TransformMismatch { .. })));
+    }
+
+    #[test]
+    fn center_is_the_middle_voxel_in_patient_space() {
+        let scan = volume([5, 5, 3], scan_transform());
+        let expected = Vector3::new(-250.0 + 2.0 * 0.98, -180.5 + 2.0 * 0.98, -420.0 + 3.0);
+        assert!((scan.center() - expected).magnitude() < 1e-4);
+    }
+}